@@ -1,12 +1,26 @@
-use tokio::net::TcpListener;
+// acronym variants (`TCP`, `HTTP`) are the naming of the whole crate; the
+// rest is how the original code is written
+#![allow(
+    clippy::upper_case_acronyms,
+    clippy::redundant_field_names,
+    clippy::unnecessary_lazy_evaluations,
+    clippy::redundant_locals
+)]
+
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 mod rinha_ambulance;
+mod rinha_balancer;
 mod rinha_chan;
 mod rinha_conf;
+mod rinha_dedup;
 mod rinha_domain;
+mod rinha_events;
+mod rinha_federation;
 mod rinha_http;
+mod rinha_metrics;
 mod rinha_net;
+mod rinha_status;
 mod rinha_storage;
 mod rinha_worker;
 
@@ -21,17 +35,35 @@ enum MainError {
     AcceptLoop(#[from] rinha_net::AcceptLoopError),
     #[error("ambulance")]
     Ambulance(#[from] rinha_ambulance::BootstrapError),
-    #[error("resolve socket")]
-    ResolveSocket(#[from] rinha_net::ResolveSocketAddrError),
-    #[error("create socket")]
-    CreateSocket(#[from] rinha_net::CreateTCPSocketError),
+    #[error("balancer")]
+    Balancer(#[from] rinha_balancer::BootstrapError),
+    #[error("storage")]
+    Storage(#[from] rinha_storage::BootstrapError),
+    #[error("bind")]
+    Bind(#[from] rinha_net::BindError),
+    #[error("storage sync")]
+    StorageSync(#[from] rinha_storage::StorageError),
+    #[error("unknown mode {0}")]
+    UnknownMode(String),
 }
 
 async fn run() -> Result<(), MainError> {
     rinha_net::bootstrap();
-    rinha_chan::boostrap();
     rinha_conf::bootstrap();
-    rinha_storage::bootstrap();
+
+    match rinha_conf::RINHA_MODE.as_str() {
+        "server" => run_server().await,
+        "balancer" => run_balancer().await,
+        mode => Err(MainError::UnknownMode(mode.into())),
+    }
+}
+
+async fn run_server() -> Result<(), MainError> {
+    rinha_chan::boostrap();
+    rinha_dedup::bootstrap();
+    rinha_status::bootstrap();
+    rinha_events::bootstrap();
+    rinha_storage::bootstrap()?;
     rinha_ambulance::bootstrap().await?;
 
     {
@@ -44,13 +76,104 @@ async fn run() -> Result<(), MainError> {
         tokio::spawn(ambulance_task);
     }
 
-    let addr = rinha_net::resolve_socket_addr(rinha_conf::RINHA_ADDR.as_str()).await?;
-    let tcp_socket = rinha_net::create_tcp_socket(addr)?;
-    let tcp_listener = TcpListener::from_std(tcp_socket.into())?;
+    {
+        let storage_task = rinha_storage::task();
+        tokio::spawn(storage_task);
+    }
+
+    {
+        let chan_task = rinha_chan::task();
+        tokio::spawn(chan_task);
+    }
+
+    {
+        let status_task = rinha_status::task();
+        tokio::spawn(status_task);
+    }
+
+    serve(rinha_net::router).await?;
+
+    rinha_storage::get_storage().sync()?;
+    tracing::info!("server stopped");
+
+    Ok(())
+}
+
+/// Stands in front of `RINHA_BACKENDS` instead of running one: no channels,
+/// workers or storage, just forwarding.
+async fn run_balancer() -> Result<(), MainError> {
+    rinha_balancer::bootstrap().await?;
+
+    {
+        let balancer_task = rinha_balancer::task();
+        tokio::spawn(balancer_task);
+    }
+
+    serve(rinha_balancer::forward).await?;
+
+    tracing::info!("balancer stopped");
+
+    Ok(())
+}
+
+/// Runs one accept loop per `RINHA_THREADS` with `handler` until shutdown.
+async fn serve(handler: impl rinha_net::Handler) -> Result<(), MainError> {
+    let mut listeners = rinha_net::bind(
+        rinha_conf::RINHA_LISTEN.as_str(),
+        *rinha_conf::RINHA_THREADS,
+    )
+    .await?;
+    let listener = listeners.pop().expect("bound no listener");
+
+    let servers = listeners
+        .into_iter()
+        .enumerate()
+        .map(|(index, bound)| spawn_server(index + 1, bound, handler.clone()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    {
+        let net_task = rinha_net::task();
+        tokio::spawn(net_task);
+    }
+
+    let accept_loop = rinha_net::accept_loop(listener.listen()?, handler);
+    tokio::spawn(accept_loop).await??;
+
+    for server in servers {
+        // a server thread that failed already exited the process
+        let _ = tokio::task::spawn_blocking(move || server.join()).await?;
+    }
+
+    Ok(())
+}
+
+/// Serves `bound` from a thread of its own, under a `current_thread` runtime
+/// like the main one. Only the main runtime runs the background tasks; the
+/// state they share with the handlers is process-wide.
+fn spawn_server(
+    index: usize,
+    bound: rinha_net::Bound,
+    handler: impl rinha_net::Handler,
+) -> Result<std::thread::JoinHandle<()>, std::io::Error> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    let server = std::thread::Builder::new()
+        .name(format!("rinha-server-{index}"))
+        .spawn(move || {
+            let res = runtime.block_on(async {
+                rinha_net::accept_loop(bound.listen()?, handler).await?;
+                Ok::<_, MainError>(())
+            });
 
-    let accept_loop = rinha_net::accept_loop(tcp_listener);
+            if let Err(err) = res {
+                tracing::error!(?err, "aborting server...");
+                std::process::exit(1);
+            }
+        })?;
 
-    Ok(tokio::spawn(accept_loop).await??)
+    Ok(server)
 }
 
 #[tokio::main(flavor = "current_thread")]
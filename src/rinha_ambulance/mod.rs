@@ -1,10 +1,11 @@
 use crate::rinha_domain::Health;
-use crate::rinha_net;
 use crate::{rinha_conf, rinha_net::resolve_socket_addr};
+use crate::{rinha_metrics, rinha_net};
 use dashmap::DashMap;
 use http::{Extensions, Method, Request};
 use http_body_util::{BodyExt, Full};
 use hyper::body::Bytes;
+use serde::Serialize;
 use std::hash::{DefaultHasher, Hash, Hasher};
 use std::net::SocketAddr;
 use std::sync::{Arc, LazyLock};
@@ -18,9 +19,11 @@ static FALLBACK_UPSTREAM: OnceCell<Upstream> = OnceCell::const_new();
 
 static HEALTH_MAP: LazyLock<Arc<HealthMap>> = LazyLock::new(|| Arc::new(HealthMap::new()));
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize)]
 pub enum UpstreamType {
+    #[serde(rename = "default")]
     Default,
+    #[serde(rename = "fallback")]
     Fallback,
 }
 
@@ -108,6 +111,15 @@ async fn check() -> Result<(), CheckError> {
     health_map.insert(default_upstream.hash_addr(), !default_stats.failing);
     health_map.insert(fallback_upstream.hash_addr(), !fallback_stats.failing);
 
+    rinha_metrics::upstream_min_response_time(
+        &UpstreamType::Default,
+        default_stats.min_response_time,
+    );
+    rinha_metrics::upstream_min_response_time(
+        &UpstreamType::Fallback,
+        fallback_stats.min_response_time,
+    );
+
     Ok(())
 }
 
@@ -124,6 +136,37 @@ pub async fn select<'a>() -> Option<&'a Upstream> {
     None
 }
 
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct UpstreamsHealth {
+    pub resolved: bool,
+    /// `None` until the first check answered
+    pub default: Option<bool>,
+    pub fallback: Option<bool>,
+}
+
+impl UpstreamsHealth {
+    pub fn any_healthy(&self) -> bool {
+        self.default == Some(true) || self.fallback == Some(true)
+    }
+}
+
+pub fn health() -> UpstreamsHealth {
+    let Some((default_upstream, fallback_upstream)) = get_upstreams() else {
+        return UpstreamsHealth {
+            resolved: false,
+            default: None,
+            fallback: None,
+        };
+    };
+    let health_map = get_health_map();
+
+    UpstreamsHealth {
+        resolved: true,
+        default: health_map.get(&default_upstream.hash_addr()).map(|h| *h),
+        fallback: health_map.get(&fallback_upstream.hash_addr()).map(|h| *h),
+    }
+}
+
 pub fn get_health_map() -> Arc<HealthMap> {
     HEALTH_MAP.clone()
 }
@@ -0,0 +1,129 @@
+use super::{BackendAddr, backends};
+use crate::rinha_net;
+use hyper::{
+    Uri,
+    rt::{Read, ReadBufCursor, Write},
+};
+use hyper_util::{
+    client::legacy::connect::{Connected, Connection, HttpConnector},
+    rt::TokioIo,
+};
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::net::{TcpStream, UnixStream};
+use tower_service::Service;
+
+/// Authority of the URIs sent to backend `index`; the connector maps it back
+/// to the backend's address, so TCP and Unix backends share one client pool.
+pub fn authority(index: usize) -> String {
+    format!("backend-{index}")
+}
+
+fn backend_index(uri: &Uri) -> Option<usize> {
+    uri.host()?.strip_prefix("backend-")?.parse().ok()
+}
+
+#[derive(Debug, Clone)]
+pub struct BackendConnector {
+    http: HttpConnector,
+}
+
+impl BackendConnector {
+    pub fn new() -> Self {
+        Self {
+            http: rinha_net::http_connector(),
+        }
+    }
+}
+
+impl Service<Uri> for BackendConnector {
+    type Response = BackendStream;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<BackendStream, io::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let mut http = self.http.clone();
+
+        Box::pin(async move {
+            let backend = backend_index(&uri)
+                .and_then(|index| backends().get(index))
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "unknown backend"))?;
+
+            match &backend.addr {
+                BackendAddr::TCP(addr) => {
+                    let uri = format!("http://{addr}")
+                        .parse::<Uri>()
+                        .map_err(io::Error::other)?;
+                    let stream = http.call(uri).await.map_err(io::Error::other)?;
+
+                    Ok(BackendStream::TCP(stream))
+                }
+                BackendAddr::Unix(path) => Ok(BackendStream::Unix(TokioIo::new(
+                    UnixStream::connect(path).await?,
+                ))),
+            }
+        })
+    }
+}
+
+pub enum BackendStream {
+    TCP(TokioIo<TcpStream>),
+    Unix(TokioIo<UnixStream>),
+}
+
+impl Connection for BackendStream {
+    fn connected(&self) -> Connected {
+        match self {
+            Self::TCP(stream) => stream.connected(),
+            Self::Unix(stream) => stream.connected(),
+        }
+    }
+}
+
+impl Read for BackendStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: ReadBufCursor<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::TCP(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl Write for BackendStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::TCP(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::TCP(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::TCP(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
@@ -0,0 +1,365 @@
+use crate::{
+    rinha_conf, rinha_http,
+    rinha_net::{self, Guarded, UNIX_PREFIX},
+};
+use http_body_util::{BodyExt, Either, Full, Limited};
+use hyper::{
+    HeaderMap, Method, Request, Response, StatusCode,
+    body::{Bytes, Incoming},
+    header,
+};
+use hyper_util::client::legacy::Client;
+use std::{
+    convert::Infallible,
+    fmt,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        LazyLock, OnceLock,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+};
+use tokio::{
+    task::JoinSet,
+    time::{Duration, interval, timeout},
+};
+
+mod connector;
+
+use connector::{BackendConnector, authority};
+
+/// Headers that describe one hop only and are never forwarded.
+const HOP_BY_HOP: [header::HeaderName; 6] = [
+    header::CONNECTION,
+    header::PROXY_AUTHORIZATION,
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BalancePolicy {
+    /// each backend in turn
+    RoundRobin,
+    /// the backend with the fewest requests awaiting an answer
+    LeastOutstanding,
+}
+
+impl BalancePolicy {
+    pub fn parse(policy: &str) -> Self {
+        match policy {
+            "least-outstanding" => Self::LeastOutstanding,
+            _ => Self::RoundRobin,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BackendAddr {
+    TCP(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl fmt::Display for BackendAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TCP(addr) => write!(f, "{addr}"),
+            Self::Unix(path) => write!(f, "{UNIX_PREFIX}{}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Backend {
+    pub addr: BackendAddr,
+    authority: String,
+    /// requests sent and not answered yet
+    outstanding: AtomicUsize,
+    /// assumed until the first check says otherwise
+    healthy: AtomicBool,
+}
+
+/// Counts a request against its backend until dropped, which is once the
+/// response body has been relayed, not when its headers arrive.
+pub struct Outstanding<'a>(&'a Backend);
+
+impl<'a> Outstanding<'a> {
+    fn new(backend: &'a Backend) -> Self {
+        backend.outstanding.fetch_add(1, Ordering::Relaxed);
+        Self(backend)
+    }
+}
+
+impl Drop for Outstanding<'_> {
+    fn drop(&mut self) {
+        self.0.outstanding.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+static BACKENDS: OnceLock<Vec<Backend>> = OnceLock::new();
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+static CLIENT: LazyLock<Client<BackendConnector, Full<Bytes>>> = LazyLock::new(|| {
+    let mut client = rinha_net::client_builder();
+    client.pool_max_idle_per_host(*rinha_conf::RINHA_BACKEND_POOL_MAX_IDLE);
+    // a pooled connection the backend closed meanwhile is safe to retry
+    client.retry_canceled_requests(true);
+
+    client.build(BackendConnector::new())
+});
+
+pub fn backends<'a>() -> &'a [Backend] {
+    BACKENDS.get().map(Vec::as_slice).unwrap_or_default()
+}
+
+/// Picks among the healthy backends not `tried` yet, rotating the starting
+/// point so that ties spread evenly.
+fn select(tried: &[bool]) -> Option<usize> {
+    let backends = backends();
+    let count = backends.len();
+    let start = NEXT.fetch_add(1, Ordering::Relaxed);
+    let mut candidates = (0..count)
+        .map(|offset| (start + offset) % count)
+        .filter(|index| !tried[*index] && backends[*index].healthy.load(Ordering::Relaxed));
+
+    match BalancePolicy::parse(rinha_conf::RINHA_BALANCE_POLICY.as_str()) {
+        BalancePolicy::RoundRobin => candidates.next(),
+        BalancePolicy::LeastOutstanding => {
+            candidates.min_by_key(|index| backends[*index].outstanding.load(Ordering::Relaxed))
+        }
+    }
+}
+
+/// Largest body relayed to a backend, that of the largest batch any of them
+/// accepts; it is buffered whole before a backend is picked.
+const MAX_BODY_BYTES: usize = rinha_http::MAX_BATCH_BYTES;
+
+/// Drops the headers meant for a single hop, those the `connection` header
+/// names included.
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let named: Vec<header::HeaderName> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|token| header::HeaderName::try_from(token.trim()).ok())
+        .collect();
+    for name in &named {
+        headers.remove(name);
+    }
+
+    for name in &HOP_BY_HOP {
+        headers.remove(name);
+    }
+    headers.remove("keep-alive");
+    headers.remove("proxy-connection");
+}
+
+/// Responses come from a backend as they are, or are errors of our own.
+pub type BalancerBody = Either<Full<Bytes>, Guarded<Incoming, Outstanding<'static>>>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ForwardError {
+    #[error("hyper")]
+    Hyper(#[from] hyper::Error),
+    #[error("http")]
+    HTTP(#[from] http::Error),
+    #[error("client")]
+    Client(#[from] hyper_util::client::legacy::Error),
+
+    #[error("no healthy backend")]
+    NoBackend,
+    #[error("more than {0} bytes")]
+    BodyTooLarge(usize),
+}
+
+impl ForwardError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Hyper(_) => StatusCode::BAD_REQUEST,
+            Self::HTTP(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Client(_) => StatusCode::BAD_GATEWAY,
+            Self::NoBackend => StatusCode::SERVICE_UNAVAILABLE,
+            Self::BodyTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+        }
+    }
+}
+
+/// Relays `req` to a backend. The body is buffered first, so a backend that
+/// refuses the connection, and thus never saw the request, is marked down
+/// and the next one tried.
+async fn try_forward(req: Request<Incoming>) -> Result<Response<BalancerBody>, ForwardError> {
+    let (parts, body) = req.into_parts();
+    // `Limited` fails with either the body's own error or the limit's
+    let body = Limited::new(body, MAX_BODY_BYTES)
+        .collect()
+        .await
+        .map_err(|err| match err.downcast::<hyper::Error>() {
+            Ok(err) => ForwardError::Hyper(*err),
+            Err(_) => ForwardError::BodyTooLarge(MAX_BODY_BYTES),
+        })?
+        .to_bytes();
+    let path = parts
+        .uri
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+    let mut tried = vec![false; backends().len()];
+
+    loop {
+        let index = select(&tried).ok_or(ForwardError::NoBackend)?;
+        let backend = &backends()[index];
+        tried[index] = true;
+
+        let mut req = Request::builder()
+            .method(parts.method.clone())
+            .uri(format!("http://{}{path}", backend.authority))
+            .body(Full::new(body.clone()))?;
+        *req.headers_mut() = parts.headers.clone();
+        strip_hop_by_hop(req.headers_mut());
+
+        let outstanding = Outstanding::new(backend);
+
+        match CLIENT.request(req).await {
+            Ok(mut res) => {
+                strip_hop_by_hop(res.headers_mut());
+                return Ok(res.map(|body| Either::Right(Guarded::new(body, outstanding))));
+            }
+            Err(err) if err.is_connect() => {
+                tracing::warn!(?err, backend = %backend.addr, "backend unreachable");
+                backend.healthy.store(false, Ordering::Relaxed);
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Handler of the accept loops in balancer mode.
+pub async fn forward(req: Request<Incoming>) -> Result<Response<BalancerBody>, Infallible> {
+    let res = try_forward(req).await.unwrap_or_else(|err| {
+        let status = err.status();
+        if status.is_server_error() {
+            tracing::error!(?err, "balancer");
+        }
+
+        let message = status.canonical_reason().unwrap_or("error").to_lowercase();
+        rinha_http::error_response(status, &message).map(Either::Left)
+    });
+
+    Ok(res)
+}
+
+async fn check(backend: &Backend) -> bool {
+    let Ok(req) = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}/readyz", backend.authority))
+        .body(Full::new(Bytes::new()))
+    else {
+        return false;
+    };
+
+    let probe = async {
+        let res = CLIENT.request(req).await.ok()?;
+        let status = res.status();
+        // read to the end so the connection goes back to the pool
+        res.into_body().collect().await.ok()?;
+
+        Some(status.is_success())
+    };
+
+    let timeout_ms = Duration::from_millis(*rinha_conf::RINHA_BACKEND_TIMEOUT_MS);
+    matches!(timeout(timeout_ms, probe).await, Ok(Some(true)))
+}
+
+/// Probes the `/readyz` of every backend each
+/// `RINHA_BACKEND_CHECK_INTERVAL_MS`; a backend takes traffic for as long as
+/// it answers ready in time, being merely alive is not enough.
+pub async fn task() {
+    let mut ticker = interval(Duration::from_millis(
+        *rinha_conf::RINHA_BACKEND_CHECK_INTERVAL_MS,
+    ));
+
+    loop {
+        ticker.tick().await;
+
+        let mut checks = JoinSet::new();
+        for backend in backends() {
+            checks.spawn(async move { (backend, check(backend).await) });
+        }
+
+        while let Some(Ok((backend, healthy))) = checks.join_next().await {
+            if backend.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+                tracing::info!(backend = %backend.addr, healthy, "backend health");
+            }
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum BootstrapError {
+    #[error("sockaddr")]
+    SockAddr(#[from] rinha_net::ResolveSocketAddrError),
+    #[error("no backends")]
+    NoBackends,
+    #[error("already bootstrapped")]
+    AlreadyBootstrapped,
+}
+
+/// Resolves `RINHA_BACKENDS`, each `host:port` or `unix:<path>`.
+pub async fn bootstrap() -> Result<(), BootstrapError> {
+    let mut backends = Vec::with_capacity(rinha_conf::RINHA_BACKENDS.len());
+
+    for (index, backend) in rinha_conf::RINHA_BACKENDS.iter().enumerate() {
+        let addr = match backend.strip_prefix(UNIX_PREFIX) {
+            Some(path) => BackendAddr::Unix(PathBuf::from(path)),
+            None => BackendAddr::TCP(rinha_net::resolve_socket_addr(backend.as_str()).await?),
+        };
+
+        backends.push(Backend {
+            addr,
+            authority: authority(index),
+            outstanding: AtomicUsize::new(0),
+            healthy: AtomicBool::new(true),
+        });
+    }
+
+    if backends.is_empty() {
+        return Err(BootstrapError::NoBackends);
+    }
+
+    BACKENDS
+        .set(backends)
+        .map_err(|_| BootstrapError::AlreadyBootstrapped)?;
+    LazyLock::force(&CLIENT);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    #[test]
+    fn strips_headers_named_by_connection() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONNECTION,
+            HeaderValue::from_static("X-Trace, keep-alive"),
+        );
+        headers.append(header::CONNECTION, HeaderValue::from_static("x-hop"));
+        headers.insert("x-trace", HeaderValue::from_static("1"));
+        headers.insert("x-hop", HeaderValue::from_static("1"));
+        headers.insert("keep-alive", HeaderValue::from_static("timeout=5"));
+        headers.insert(header::TE, HeaderValue::from_static("trailers"));
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+
+        strip_hop_by_hop(&mut headers);
+
+        assert_eq!(headers.len(), 1);
+        assert!(headers.contains_key(header::CONTENT_TYPE));
+    }
+}
@@ -1,21 +1,61 @@
 use crate::rinha_domain::Payment;
-use std::sync::{LazyLock, atomic};
-use tokio::sync::{Mutex, mpsc};
+use serde::Serialize;
+use std::sync::{LazyLock, PoisonError, RwLock, atomic};
+use tokio::{
+    sync::{Mutex, mpsc},
+    time::{Duration, interval},
+};
 
-pub type PaymentSendError = mpsc::error::SendError<Payment>;
-pub type PaymentTrySendError = mpsc::error::TrySendError<Payment>;
-pub type PaymentReceiver = mpsc::Receiver<Payment>;
-pub type PaymentSender = mpsc::Sender<Payment>;
+pub type PaymentSendError = mpsc::error::SendError<Queued>;
+pub type PaymentTrySendError = mpsc::error::TrySendError<Queued>;
+pub type PaymentReceiver = mpsc::Receiver<Queued>;
+pub type PaymentSender = mpsc::Sender<Queued>;
+
+/// A payment stamped with the epoch it was enqueued in; once a purge
+/// advances the epoch it is stale and never reaches the ledger.
+#[derive(Debug)]
+pub struct Queued {
+    pub epoch: u64,
+    pub payment: Payment,
+}
+
+impl Queued {
+    pub fn new(payment: Payment) -> Self {
+        Self {
+            epoch: EPOCH.load(atomic::Ordering::Acquire),
+            payment,
+        }
+    }
+
+    pub fn is_current(&self) -> bool {
+        self.epoch == EPOCH.load(atomic::Ordering::Acquire)
+    }
+}
+
+pub const INTERNAL_QUEUES_PATH: &str = "/internal/queues";
 
 const CHANNEL_BUFFER: usize = 256 << 8;
 const CHANNEL_COUNT: usize = 5;
 
 static COUNTER: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+/// payments taken off the channels by workers
+static DRAINED: atomic::AtomicU64 = atomic::AtomicU64::new(0);
+/// payments refused because every channel was full
+static REJECTED: atomic::AtomicU64 = atomic::AtomicU64::new(0);
+/// `DRAINED` per second over the last sampling interval
+static DRAIN_RATE: atomic::AtomicU64 = atomic::AtomicU64::new(0);
+
+const RETRY_AFTER_MAX: Duration = Duration::from_secs(30);
+
+static EPOCH: atomic::AtomicU64 = atomic::AtomicU64::new(0);
+/// Held shared while a payment is recorded and exclusively while the epoch
+/// advances, so no stale payment is recorded after a purge.
+static GATE: RwLock<()> = RwLock::new(());
 static CHANNELS: LazyLock<[(PaymentSender, Mutex<PaymentReceiver>); CHANNEL_COUNT]> =
     LazyLock::new(|| {
         let channels: Vec<(PaymentSender, Mutex<PaymentReceiver>)> = (0..CHANNEL_COUNT)
             .map(|_| {
-                let channel = mpsc::channel::<Payment>(CHANNEL_BUFFER);
+                let channel = mpsc::channel::<Queued>(CHANNEL_BUFFER);
                 (channel.0, Mutex::new(channel.1))
             })
             .collect();
@@ -23,15 +63,110 @@ static CHANNELS: LazyLock<[(PaymentSender, Mutex<PaymentReceiver>); CHANNEL_COUN
         channels.try_into().expect("failed to convert channels")
     });
 
-pub fn get_sender() -> PaymentSender {
-    let idx = COUNTER.fetch_add(1, atomic::Ordering::Relaxed) % CHANNEL_COUNT;
-    CHANNELS[idx].0.clone()
+/// Offers `queued` to every channel, starting from the next round-robin one,
+/// and only gives it back as `Full` once none of them has room.
+pub fn try_send(mut queued: Queued) -> Result<(), PaymentTrySendError> {
+    let start = COUNTER.fetch_add(1, atomic::Ordering::Relaxed);
+
+    for offset in 0..CHANNEL_COUNT {
+        match CHANNELS[(start + offset) % CHANNEL_COUNT]
+            .0
+            .try_send(queued)
+        {
+            Ok(()) => return Ok(()),
+            Err(mpsc::error::TrySendError::Full(rejected)) => queued = rejected,
+            Err(err) => return Err(err),
+        }
+    }
+
+    REJECTED.fetch_add(1, atomic::Ordering::Relaxed);
+
+    Err(mpsc::error::TrySendError::Full(queued))
+}
+
+pub fn drained() {
+    DRAINED.fetch_add(1, atomic::Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct QueueStats {
+    pub depth: usize,
+    pub capacity: usize,
+    #[serde(rename = "drainRate")]
+    pub drain_rate: u64,
+    pub rejected: u64,
+}
+
+pub fn stats() -> QueueStats {
+    let free: usize = CHANNELS.iter().map(|(sender, _)| sender.capacity()).sum();
+    let capacity = CHANNEL_BUFFER * CHANNEL_COUNT;
+
+    QueueStats {
+        depth: capacity - free,
+        capacity,
+        drain_rate: DRAIN_RATE.load(atomic::Ordering::Relaxed),
+        rejected: REJECTED.load(atomic::Ordering::Relaxed),
+    }
+}
+
+/// Payments waiting in each channel, in channel order.
+pub fn depths() -> [usize; CHANNEL_COUNT] {
+    std::array::from_fn(|i| CHANNEL_BUFFER - CHANNELS[i].0.capacity())
+}
+
+/// How long the queued payments take to drain at the current rate, at
+/// least a second and at most `RETRY_AFTER_MAX`.
+pub fn retry_after() -> Duration {
+    let stats = stats();
+
+    if stats.drain_rate == 0 {
+        return RETRY_AFTER_MAX;
+    }
+
+    Duration::from_secs((stats.depth as u64).div_ceil(stats.drain_rate))
+        .clamp(Duration::from_secs(1), RETRY_AFTER_MAX)
+}
+
+/// Samples the drain rate once a second.
+pub async fn task() {
+    let mut ticker = interval(Duration::from_secs(1));
+    let mut last = DRAINED.load(atomic::Ordering::Relaxed);
+
+    loop {
+        ticker.tick().await;
+
+        let drained = DRAINED.load(atomic::Ordering::Relaxed);
+        DRAIN_RATE.store(drained - last, atomic::Ordering::Relaxed);
+        last = drained;
+    }
 }
 
 pub fn get_channels<'a>() -> &'a [(PaymentSender, Mutex<PaymentReceiver>); CHANNEL_COUNT] {
     &CHANNELS
 }
 
+/// Runs `record` unless `queued` went stale, returning whether it ran.
+pub fn record_if_current(queued: &Queued, record: impl FnOnce()) -> bool {
+    let _gate = GATE.read().unwrap_or_else(PoisonError::into_inner);
+
+    if !queued.is_current() {
+        return false;
+    }
+
+    record();
+    true
+}
+
+/// Makes every queued and in-flight payment stale, then runs `reset` before
+/// any of them could still be recorded. Recording waits on `reset`, on the
+/// runtime threads, so it must not block on anything but memory.
+pub fn advance<T>(reset: impl FnOnce() -> T) -> T {
+    let _gate = GATE.write().unwrap_or_else(PoisonError::into_inner);
+    EPOCH.fetch_add(1, atomic::Ordering::AcqRel);
+
+    reset()
+}
+
 pub fn boostrap() {
     LazyLock::force(&CHANNELS);
 }
@@ -1,11 +1,98 @@
 use std::{env, sync::LazyLock};
 
+/// `server` runs a rinha instance; `balancer` only forwards to
+/// `RINHA_BACKENDS`.
+pub static RINHA_MODE: LazyLock<String> =
+    LazyLock::new(|| env::var("RINHA_MODE").unwrap_or("server".into()));
+
 pub static RINHA_HOST: LazyLock<String> =
     LazyLock::new(|| env::var("RINHA_HOST").unwrap_or("0.0.0.0".into()));
 pub static RINHA_PORT: LazyLock<String> =
     LazyLock::new(|| env::var("RINHA_PORT").unwrap_or("9999".into()));
 pub static RINHA_ADDR: LazyLock<String> =
     LazyLock::new(|| format!("{}:{}", *RINHA_HOST, *RINHA_PORT));
+/// `host:port`, or `unix:<path>` to serve on a Unix domain socket.
+pub static RINHA_LISTEN: LazyLock<String> =
+    LazyLock::new(|| env::var("RINHA_LISTEN").unwrap_or_else(|_| RINHA_ADDR.clone()));
+/// Threads serving requests, each with its own runtime and listener; the
+/// CPU quota by default.
+pub static RINHA_THREADS: LazyLock<usize> = LazyLock::new(|| {
+    env::var("RINHA_THREADS")
+        .ok()
+        .and_then(|threads| threads.parse().ok())
+        .or_else(|| std::thread::available_parallelism().ok().map(usize::from))
+        .unwrap_or(1usize)
+        .max(1)
+});
+/// Octal permissions of the `unix:` socket file.
+pub static RINHA_UNIX_SOCKET_MODE: LazyLock<u32> = LazyLock::new(|| {
+    env::var("RINHA_UNIX_SOCKET_MODE")
+        .ok()
+        .and_then(|mode| u32::from_str_radix(mode.trim_start_matches("0o"), 8).ok())
+        .unwrap_or(0o660)
+        & 0o777
+});
+
+pub static RINHA_HTTP_KEEP_ALIVE: LazyLock<bool> = LazyLock::new(|| {
+    env::var("RINHA_HTTP_KEEP_ALIVE")
+        .ok()
+        .and_then(|keep_alive| keep_alive.parse().ok())
+        .unwrap_or(true)
+});
+/// How long a kept-alive connection may go with no request in flight.
+pub static RINHA_HTTP_IDLE_TIMEOUT_MS: LazyLock<u64> = LazyLock::new(|| {
+    env::var("RINHA_HTTP_IDLE_TIMEOUT_MS")
+        .ok()
+        .and_then(|timeout| timeout.parse().ok())
+        .unwrap_or(5_000u64)
+        .max(1)
+});
+/// Requests served on one connection before it is closed; 0 for no limit.
+pub static RINHA_HTTP_MAX_REQUESTS: LazyLock<u64> = LazyLock::new(|| {
+    env::var("RINHA_HTTP_MAX_REQUESTS")
+        .ok()
+        .and_then(|max| max.parse().ok())
+        .unwrap_or(0u64)
+});
+/// How long a client has to send a complete request head, counted from its
+/// first byte; the wait before it falls under the idle timeout.
+pub static RINHA_HTTP_HEADER_TIMEOUT_MS: LazyLock<u64> = LazyLock::new(|| {
+    env::var("RINHA_HTTP_HEADER_TIMEOUT_MS")
+        .ok()
+        .and_then(|timeout| timeout.parse().ok())
+        .unwrap_or(100u64)
+        .max(1)
+});
+/// Largest request head hyper buffers; it refuses less than 8 KiB.
+pub static RINHA_HTTP_MAX_BUF_SIZE: LazyLock<usize> = LazyLock::new(|| {
+    env::var("RINHA_HTTP_MAX_BUF_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(16usize << 10)
+        .max(8 << 10)
+});
+/// `SO_SNDBUF` and `SO_RCVBUF` of accepted connections.
+pub static RINHA_SOCKET_BUFFER_BYTES: LazyLock<usize> = LazyLock::new(|| {
+    env::var("RINHA_SOCKET_BUFFER_BYTES")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(32usize << 10)
+});
+/// Streams one HTTP/2 connection may have open at once.
+pub static RINHA_HTTP2_MAX_CONCURRENT_STREAMS: LazyLock<u32> = LazyLock::new(|| {
+    env::var("RINHA_HTTP2_MAX_CONCURRENT_STREAMS")
+        .ok()
+        .and_then(|max| max.parse().ok())
+        .unwrap_or(256u32)
+        .max(1)
+});
+/// How long open connections get to finish once shutdown begins.
+pub static RINHA_SHUTDOWN_GRACE_MS: LazyLock<u64> = LazyLock::new(|| {
+    env::var("RINHA_SHUTDOWN_GRACE_MS")
+        .ok()
+        .and_then(|grace| grace.parse().ok())
+        .unwrap_or(5_000u64)
+});
 
 pub static RINHA_DEFAULT_UPSTREAM_HOST: LazyLock<String> =
     LazyLock::new(|| env::var("RINHA_DEFAULT_UPSTREAM_HOST").unwrap_or("127.0.0.1".into()));
@@ -29,14 +116,224 @@ pub static RINHA_FALLBACK_UPSTREAM_ADDR: LazyLock<String> = LazyLock::new(|| {
     )
 });
 
+pub static RINHA_MONEY_SCALE: LazyLock<u32> = LazyLock::new(|| {
+    env::var("RINHA_MONEY_SCALE")
+        .ok()
+        .and_then(|scale| scale.parse().ok())
+        .unwrap_or(2u32)
+        .min(18)
+});
+
+pub static RINHA_STORAGE: LazyLock<String> =
+    LazyLock::new(|| env::var("RINHA_STORAGE").unwrap_or("memory".into()));
+pub static RINHA_DATA_DIR: LazyLock<String> =
+    LazyLock::new(|| env::var("RINHA_DATA_DIR").unwrap_or("data".into()));
+pub static RINHA_SHM_PATH: LazyLock<String> =
+    LazyLock::new(|| env::var("RINHA_SHM_PATH").unwrap_or("/dev/shm/rinha.ledger".into()));
+pub static RINHA_SHM_CAPACITY: LazyLock<u64> = LazyLock::new(|| {
+    env::var("RINHA_SHM_CAPACITY")
+        .ok()
+        .and_then(|capacity| capacity.parse().ok())
+        .unwrap_or(1u64 << 19)
+        .max(1)
+});
+pub static RINHA_WAL_FSYNC: LazyLock<String> =
+    LazyLock::new(|| env::var("RINHA_WAL_FSYNC").unwrap_or("interval".into()));
+pub static RINHA_WAL_FSYNC_INTERVAL_MS: LazyLock<u64> = LazyLock::new(|| {
+    env::var("RINHA_WAL_FSYNC_INTERVAL_MS")
+        .ok()
+        .and_then(|interval| interval.parse().ok())
+        .unwrap_or(1000u64)
+        .max(1)
+});
+
+pub static RINHA_SNAPSHOT_INTERVAL_SECS: LazyLock<u64> = LazyLock::new(|| {
+    env::var("RINHA_SNAPSHOT_INTERVAL_SECS")
+        .ok()
+        .and_then(|interval| interval.parse().ok())
+        .unwrap_or(300u64)
+});
+pub static RINHA_SNAPSHOT_RETAIN: LazyLock<usize> = LazyLock::new(|| {
+    env::var("RINHA_SNAPSHOT_RETAIN")
+        .ok()
+        .and_then(|retain| retain.parse().ok())
+        .unwrap_or(2usize)
+        .max(1)
+});
+
+pub static RINHA_INDEX_BUCKET_MS: LazyLock<u64> = LazyLock::new(|| {
+    env::var("RINHA_INDEX_BUCKET_MS")
+        .ok()
+        .and_then(|width| width.parse().ok())
+        .unwrap_or(1000u64)
+        .max(1)
+});
+pub static RINHA_INDEX_MAX_BUCKETS: LazyLock<usize> = LazyLock::new(|| {
+    env::var("RINHA_INDEX_MAX_BUCKETS")
+        .ok()
+        .and_then(|buckets| buckets.parse().ok())
+        .unwrap_or(1usize << 20)
+});
+
+pub static RINHA_PEERS: LazyLock<Vec<String>> = LazyLock::new(|| {
+    env::var("RINHA_PEERS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|peer| !peer.is_empty())
+        .map(String::from)
+        .collect()
+});
+pub static RINHA_PEER_TIMEOUT_MS: LazyLock<u64> = LazyLock::new(|| {
+    env::var("RINHA_PEER_TIMEOUT_MS")
+        .ok()
+        .and_then(|timeout| timeout.parse().ok())
+        .unwrap_or(200u64)
+});
+pub static RINHA_PEER_POLICY: LazyLock<String> =
+    LazyLock::new(|| env::var("RINHA_PEER_POLICY").unwrap_or("skip".into()));
+
+/// Instances a balancer forwards to, each `host:port` or `unix:<path>`.
+/// Their connections are pooled, unless they run with `RINHA_HTTP_KEEP_ALIVE`
+/// off.
+pub static RINHA_BACKENDS: LazyLock<Vec<String>> = LazyLock::new(|| {
+    env::var("RINHA_BACKENDS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|backend| !backend.is_empty())
+        .map(String::from)
+        .collect()
+});
+/// `round-robin` or `least-outstanding`.
+pub static RINHA_BALANCE_POLICY: LazyLock<String> =
+    LazyLock::new(|| env::var("RINHA_BALANCE_POLICY").unwrap_or("round-robin".into()));
+pub static RINHA_BACKEND_CHECK_INTERVAL_MS: LazyLock<u64> = LazyLock::new(|| {
+    env::var("RINHA_BACKEND_CHECK_INTERVAL_MS")
+        .ok()
+        .and_then(|interval| interval.parse().ok())
+        .unwrap_or(1_000u64)
+        .max(1)
+});
+pub static RINHA_BACKEND_TIMEOUT_MS: LazyLock<u64> = LazyLock::new(|| {
+    env::var("RINHA_BACKEND_TIMEOUT_MS")
+        .ok()
+        .and_then(|timeout| timeout.parse().ok())
+        .unwrap_or(500u64)
+});
+/// Idle connections kept open to each backend.
+pub static RINHA_BACKEND_POOL_MAX_IDLE: LazyLock<usize> = LazyLock::new(|| {
+    env::var("RINHA_BACKEND_POOL_MAX_IDLE")
+        .ok()
+        .and_then(|max| max.parse().ok())
+        .unwrap_or(64usize)
+});
+
+pub static RINHA_DEDUP_TTL_SECS: LazyLock<u64> = LazyLock::new(|| {
+    env::var("RINHA_DEDUP_TTL_SECS")
+        .ok()
+        .and_then(|ttl| ttl.parse().ok())
+        .unwrap_or(600u64)
+});
+pub static RINHA_DEDUP_CAPACITY: LazyLock<usize> = LazyLock::new(|| {
+    env::var("RINHA_DEDUP_CAPACITY")
+        .ok()
+        .and_then(|capacity| capacity.parse().ok())
+        .unwrap_or(1usize << 18)
+});
+
+pub static RINHA_READY_QUEUE_PERCENT: LazyLock<u64> = LazyLock::new(|| {
+    env::var("RINHA_READY_QUEUE_PERCENT")
+        .ok()
+        .and_then(|percent| percent.parse().ok())
+        .unwrap_or(90u64)
+        .min(100)
+});
+
+pub static RINHA_STATUS_TTL_SECS: LazyLock<u64> = LazyLock::new(|| {
+    env::var("RINHA_STATUS_TTL_SECS")
+        .ok()
+        .and_then(|ttl| ttl.parse().ok())
+        .unwrap_or(600u64)
+});
+
+pub static RINHA_RETENTION_SECS: LazyLock<u64> = LazyLock::new(|| {
+    env::var("RINHA_RETENTION_SECS")
+        .ok()
+        .and_then(|retention| retention.parse().ok())
+        .unwrap_or(0u64)
+});
+pub static RINHA_ROLLUP_BUCKET_SECS: LazyLock<u64> = LazyLock::new(|| {
+    env::var("RINHA_ROLLUP_BUCKET_SECS")
+        .ok()
+        .and_then(|width| width.parse().ok())
+        .unwrap_or(60u64)
+        .max(1)
+});
+/// Bounds the ledgers only; admission dedup, payment statuses, queued
+/// payments and connection buffers are not counted, so it should stay around
+/// a third of the memory the process may use.
+pub static RINHA_LEDGER_BUDGET_BYTES: LazyLock<usize> = LazyLock::new(|| {
+    env::var("RINHA_LEDGER_BUDGET_BYTES")
+        .ok()
+        .and_then(|budget| budget.parse().ok())
+        .unwrap_or(128usize << 20)
+});
+pub static RINHA_RETENTION_INTERVAL_SECS: LazyLock<u64> = LazyLock::new(|| {
+    env::var("RINHA_RETENTION_INTERVAL_SECS")
+        .ok()
+        .and_then(|interval| interval.parse().ok())
+        .unwrap_or(10u64)
+        .max(1)
+});
+
 pub fn bootstrap() {
+    LazyLock::force(&RINHA_MODE);
     LazyLock::force(&RINHA_HOST);
     LazyLock::force(&RINHA_PORT);
     LazyLock::force(&RINHA_ADDR);
+    LazyLock::force(&RINHA_LISTEN);
+    LazyLock::force(&RINHA_THREADS);
+    LazyLock::force(&RINHA_UNIX_SOCKET_MODE);
+    LazyLock::force(&RINHA_HTTP_KEEP_ALIVE);
+    LazyLock::force(&RINHA_HTTP_IDLE_TIMEOUT_MS);
+    LazyLock::force(&RINHA_HTTP_MAX_REQUESTS);
+    LazyLock::force(&RINHA_HTTP_HEADER_TIMEOUT_MS);
+    LazyLock::force(&RINHA_HTTP_MAX_BUF_SIZE);
+    LazyLock::force(&RINHA_SOCKET_BUFFER_BYTES);
+    LazyLock::force(&RINHA_HTTP2_MAX_CONCURRENT_STREAMS);
+    LazyLock::force(&RINHA_SHUTDOWN_GRACE_MS);
     LazyLock::force(&RINHA_DEFAULT_UPSTREAM_HOST);
     LazyLock::force(&RINHA_DEFAULT_UPSTREAM_PORT);
     LazyLock::force(&RINHA_DEFAULT_UPSTREAM_ADDR);
     LazyLock::force(&RINHA_FALLBACK_UPSTREAM_HOST);
     LazyLock::force(&RINHA_FALLBACK_UPSTREAM_PORT);
     LazyLock::force(&RINHA_FALLBACK_UPSTREAM_ADDR);
+    LazyLock::force(&RINHA_MONEY_SCALE);
+    LazyLock::force(&RINHA_STORAGE);
+    LazyLock::force(&RINHA_DATA_DIR);
+    LazyLock::force(&RINHA_SHM_PATH);
+    LazyLock::force(&RINHA_SHM_CAPACITY);
+    LazyLock::force(&RINHA_WAL_FSYNC);
+    LazyLock::force(&RINHA_WAL_FSYNC_INTERVAL_MS);
+    LazyLock::force(&RINHA_SNAPSHOT_INTERVAL_SECS);
+    LazyLock::force(&RINHA_SNAPSHOT_RETAIN);
+    LazyLock::force(&RINHA_INDEX_BUCKET_MS);
+    LazyLock::force(&RINHA_INDEX_MAX_BUCKETS);
+    LazyLock::force(&RINHA_PEERS);
+    LazyLock::force(&RINHA_PEER_TIMEOUT_MS);
+    LazyLock::force(&RINHA_PEER_POLICY);
+    LazyLock::force(&RINHA_BACKENDS);
+    LazyLock::force(&RINHA_BALANCE_POLICY);
+    LazyLock::force(&RINHA_BACKEND_CHECK_INTERVAL_MS);
+    LazyLock::force(&RINHA_BACKEND_TIMEOUT_MS);
+    LazyLock::force(&RINHA_BACKEND_POOL_MAX_IDLE);
+    LazyLock::force(&RINHA_DEDUP_TTL_SECS);
+    LazyLock::force(&RINHA_DEDUP_CAPACITY);
+    LazyLock::force(&RINHA_READY_QUEUE_PERCENT);
+    LazyLock::force(&RINHA_STATUS_TTL_SECS);
+    LazyLock::force(&RINHA_RETENTION_SECS);
+    LazyLock::force(&RINHA_ROLLUP_BUCKET_SECS);
+    LazyLock::force(&RINHA_LEDGER_BUDGET_BYTES);
+    LazyLock::force(&RINHA_RETENTION_INTERVAL_SECS);
 }
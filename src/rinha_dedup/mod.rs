@@ -0,0 +1,70 @@
+use crate::rinha_conf;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{LazyLock, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+/// Correlation ids admitted at ingestion, oldest first, so expiry and the
+/// capacity bound both evict from the front.
+#[derive(Debug, Default)]
+struct Seen {
+    ids: HashMap<Uuid, Instant>,
+    order: VecDeque<(Instant, Uuid)>,
+}
+
+impl Seen {
+    fn evict(&mut self, now: Instant) {
+        let ttl = Duration::from_secs(*rinha_conf::RINHA_DEDUP_TTL_SECS);
+        let capacity = *rinha_conf::RINHA_DEDUP_CAPACITY;
+
+        while let Some((admitted_at, id)) = self.order.front().copied() {
+            if now.duration_since(admitted_at) < ttl && self.order.len() <= capacity {
+                break;
+            }
+
+            self.order.pop_front();
+            // the id may have been forgotten and admitted again since
+            if self.ids.get(&id) == Some(&admitted_at) {
+                self.ids.remove(&id);
+            }
+        }
+    }
+}
+
+static SEEN: LazyLock<Mutex<Seen>> = LazyLock::new(|| Mutex::new(Seen::default()));
+
+/// Returns `false` when `id` was already admitted within the last
+/// `RINHA_DEDUP_TTL_SECS`, keeping at most `RINHA_DEDUP_CAPACITY` ids.
+pub fn admit(id: Uuid) -> bool {
+    let now = Instant::now();
+    let mut seen = SEEN.lock().unwrap_or_else(PoisonError::into_inner);
+
+    seen.evict(now);
+
+    if seen.ids.contains_key(&id) {
+        return false;
+    }
+
+    seen.ids.insert(id, now);
+    seen.order.push_back((now, id));
+
+    true
+}
+
+/// Un-admits `id`, for payments that were never enqueued after all.
+pub fn forget(id: Uuid) {
+    let mut seen = SEEN.lock().unwrap_or_else(PoisonError::into_inner);
+    seen.ids.remove(&id);
+}
+
+/// Forgets every admitted id.
+pub fn reset() {
+    let mut seen = SEEN.lock().unwrap_or_else(PoisonError::into_inner);
+    *seen = Seen::default();
+}
+
+pub fn bootstrap() {
+    LazyLock::force(&SEEN);
+}
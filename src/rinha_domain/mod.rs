@@ -1,17 +1,175 @@
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use crate::rinha_conf;
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+use serde_json::value::RawValue;
+use std::{
+    fmt,
+    ops::{AddAssign, Sub},
+};
 use uuid::Uuid;
 
 pub fn dt_to_i64(dt: DateTime<Utc>) -> i64 {
     dt.timestamp_micros()
 }
 
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum MoneyError {
+    #[error("invalid number")]
+    Invalid,
+    #[error("more than {0} fractional digits")]
+    Precision(u32),
+    #[error("overflow")]
+    Overflow,
+    #[error("not positive")]
+    NotPositive,
+}
+
+/// Fixed-point amount stored as an integer count of `10^-RINHA_MONEY_SCALE`
+/// units, so sums are exact and serialize back to the same JSON number.
+/// Arithmetic saturates instead of wrapping, release builds do not check.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Money(i64);
+
+impl Money {
+    pub const fn from_units(units: i64) -> Self {
+        Self(units)
+    }
+
+    pub const fn units(self) -> i64 {
+        self.0
+    }
+
+    /// Parses a JSON number literal (sign, fraction and exponent allowed)
+    /// into exact units. Trailing zeros beyond the scale are accepted, any
+    /// other extra fractional digit is rejected instead of being rounded.
+    pub fn parse(literal: &str) -> Result<Self, MoneyError> {
+        let scale = *rinha_conf::RINHA_MONEY_SCALE;
+        let literal = literal.trim();
+
+        let (negative, literal) = match literal.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, literal),
+        };
+        let (mantissa, exponent) = match literal.find(['e', 'E']) {
+            Some(idx) => {
+                let exponent = literal[idx + 1..]
+                    .parse::<i32>()
+                    .map_err(|_| MoneyError::Invalid)?;
+                (&literal[..idx], exponent)
+            }
+            None => (literal, 0),
+        };
+        let (int, frac) = match mantissa.split_once('.') {
+            Some((int, frac)) if !frac.is_empty() => (int, frac),
+            Some(_) => return Err(MoneyError::Invalid),
+            None => (mantissa, ""),
+        };
+
+        if int.is_empty() || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit()) {
+            return Err(MoneyError::Invalid);
+        }
+
+        // the decimal point sits after `point` digits of `int ++ frac`
+        let digits = int.bytes().chain(frac.bytes());
+        let point = int.len() as i64 + exponent as i64;
+        let mut units: i64 = 0;
+
+        for (idx, digit) in digits.enumerate() {
+            let digit = (digit - b'0') as i64;
+            let place = idx as i64 - point + 1;
+
+            if place > scale as i64 {
+                if digit != 0 {
+                    return Err(MoneyError::Precision(scale));
+                }
+                continue;
+            }
+
+            units = units
+                .checked_mul(10)
+                .and_then(|units| units.checked_add(digit))
+                .ok_or(MoneyError::Overflow)?;
+        }
+
+        let consumed = (int.len() + frac.len()) as i64;
+        let missing = scale as i64 - (consumed - point);
+        for _ in 0..missing.max(0) {
+            if units == 0 {
+                break;
+            }
+            units = units.checked_mul(10).ok_or(MoneyError::Overflow)?;
+        }
+
+        Ok(Money(if negative { -units } else { units }))
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scale = *rinha_conf::RINHA_MONEY_SCALE;
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let units = self.0.unsigned_abs();
+
+        if scale == 0 {
+            return write!(f, "{sign}{units}");
+        }
+
+        let factor = 10u64.pow(scale);
+        write!(
+            f,
+            "{sign}{}.{:0width$}",
+            units / factor,
+            units % factor,
+            width = scale as usize
+        )
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, rhs: Money) {
+        self.0 = self.0.saturating_add(rhs.0);
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+
+    fn sub(self, rhs: Money) -> Money {
+        Money(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let raw = RawValue::from_string(self.to_string()).map_err(serde::ser::Error::custom)?;
+        raw.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = Box::<RawValue>::deserialize(deserializer)?;
+        Money::parse(raw.get()).map_err(de::Error::custom)
+    }
+}
+
+/// Totals may be zero, the amount of a single payment may not.
+fn positive<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Money, D::Error> {
+    let amount = Money::deserialize(deserializer)?;
+
+    if amount.units() <= 0 {
+        return Err(de::Error::custom(MoneyError::NotPositive));
+    }
+
+    Ok(amount)
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Payment {
     #[serde(rename = "correlationId")]
     pub correlation_id: Uuid,
-    #[serde(rename = "amount")]
-    pub amount: f64,
+    #[serde(rename = "amount", deserialize_with = "positive")]
+    pub amount: Money,
     #[serde(rename = "requestedAt", default = "Utc::now")]
     pub requested_at: DateTime<Utc>,
 }
@@ -32,10 +190,96 @@ pub struct TargetCounter {
     pub fallback: Count,
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
 pub struct Count {
     #[serde(rename = "totalRequests")]
     pub requests: u64,
     #[serde(rename = "totalAmount")]
-    pub amount: f64,
+    pub amount: Money,
+}
+
+impl AddAssign for Count {
+    fn add_assign(&mut self, rhs: Count) {
+        self.requests = self.requests.saturating_add(rhs.requests);
+        self.amount += rhs.amount;
+    }
+}
+
+impl AddAssign for TargetCounter {
+    fn add_assign(&mut self, rhs: TargetCounter) {
+        self.default += rhs.default;
+        self.fallback += rhs.fallback;
+    }
+}
+
+/// Totals of the payments requested within `start..end`, both shown in the
+/// time zone the series was asked for.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SeriesBucket {
+    #[serde(rename = "start")]
+    pub start: DateTime<FixedOffset>,
+    #[serde(rename = "end")]
+    pub end: DateTime<FixedOffset>,
+    #[serde(rename = "default")]
+    pub default: Count,
+    #[serde(rename = "fallback")]
+    pub fallback: Count,
+}
+
+/// Buckets ordered by `start`, empty ones included.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(transparent)]
+pub struct Series(pub Vec<SeriesBucket>);
+
+impl AddAssign for Series {
+    /// Merges buckets sharing a `start`, keeping the ones only `rhs` has.
+    fn add_assign(&mut self, rhs: Series) {
+        for bucket in rhs.0 {
+            match self.0.binary_search_by(|own| own.start.cmp(&bucket.start)) {
+                Ok(idx) => {
+                    self.0[idx].default += bucket.default;
+                    self.0[idx].fallback += bucket.fallback;
+                }
+                Err(idx) => self.0.insert(idx, bucket),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::error::Category;
+
+    fn payment(amount: &str) -> Result<Payment, serde_json::Error> {
+        serde_json::from_str(&format!(
+            r#"{{"correlationId":"4a7901b8-7d26-4d9d-aa19-4dc1c7cf60b3","amount":{amount}}}"#
+        ))
+    }
+
+    #[test]
+    fn payment_amount_must_be_positive() {
+        for amount in ["0", "0.00", "-0", "-19.90"] {
+            let err = payment(amount).unwrap_err();
+            assert_eq!(err.classify(), Category::Data, "{amount}");
+        }
+
+        assert!(payment("0.01").is_ok());
+    }
+
+    #[test]
+    fn totals_may_be_zero() {
+        let count: Count = serde_json::from_str(r#"{"totalRequests":0,"totalAmount":0}"#).unwrap();
+        assert_eq!(count.amount, Money::default());
+    }
+
+    #[test]
+    fn arithmetic_saturates() {
+        let mut total = Money::from_units(i64::MAX - 1);
+        total += Money::from_units(10);
+        assert_eq!(total, Money::from_units(i64::MAX));
+
+        let diff = Money::from_units(i64::MIN + 1) - Money::from_units(10);
+        assert_eq!(diff, Money::from_units(i64::MIN));
+    }
 }
@@ -0,0 +1,187 @@
+use crate::rinha_ambulance::UpstreamType;
+use chrono::{DateTime, Utc};
+use hyper::body::{Body, Bytes, Frame};
+use serde::Serialize;
+use std::{
+    convert::Infallible,
+    pin::Pin,
+    sync::{LazyLock, Mutex, PoisonError, atomic},
+    task::{Context, Poll},
+};
+use tokio::{
+    sync::mpsc,
+    time::{Duration, Interval, MissedTickBehavior, interval},
+};
+use uuid::Uuid;
+
+pub const EVENTS_PATH: &str = "/events";
+pub const EVENT_STREAM_CONTENT_TYPE: &str = "text/event-stream";
+
+/// Frames a subscriber may lag behind before it is dropped.
+const SUBSCRIBER_BUFFER: usize = 1024;
+const HEARTBEAT: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Kind {
+    #[serde(rename = "accepted")]
+    Accepted,
+    #[serde(rename = "dispatched")]
+    Dispatched,
+    #[serde(rename = "processed")]
+    Processed,
+    #[serde(rename = "retried")]
+    Retried,
+    #[serde(rename = "failed")]
+    Failed,
+}
+
+impl Kind {
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "accepted" => Some(Self::Accepted),
+            "dispatched" => Some(Self::Dispatched),
+            "processed" => Some(Self::Processed),
+            "retried" => Some(Self::Retried),
+            "failed" => Some(Self::Failed),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Accepted => "accepted",
+            Self::Dispatched => "dispatched",
+            Self::Processed => "processed",
+            Self::Retried => "retried",
+            Self::Failed => "failed",
+        }
+    }
+
+    fn bit(&self) -> u8 {
+        1 << *self as u8
+    }
+}
+
+/// Set of event kinds a subscriber asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Filter(u8);
+
+impl Filter {
+    pub const ALL: Self = Self(u8::MAX);
+
+    pub fn none() -> Self {
+        Self(0)
+    }
+
+    pub fn with(self, kind: Kind) -> Self {
+        Self(self.0 | kind.bit())
+    }
+
+    fn matches(&self, kind: Kind) -> bool {
+        self.0 & kind.bit() != 0
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Event<'a> {
+    #[serde(rename = "type")]
+    kind: &'a str,
+    #[serde(rename = "correlationId")]
+    correlation_id: Uuid,
+    #[serde(rename = "processor", skip_serializing_if = "Option::is_none")]
+    processor: Option<&'a UpstreamType>,
+    #[serde(rename = "attempt", skip_serializing_if = "Option::is_none")]
+    attempt: Option<u32>,
+    #[serde(rename = "at")]
+    at: DateTime<Utc>,
+}
+
+struct Subscriber {
+    filter: Filter,
+    sender: mpsc::Sender<Bytes>,
+}
+
+static SUBSCRIBERS: LazyLock<Mutex<Vec<Subscriber>>> = LazyLock::new(|| Mutex::new(Vec::new()));
+/// mirrors `SUBSCRIBERS.len()` so publishing without listeners stays lock-free
+static SUBSCRIBER_COUNT: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+
+/// Fans the event out to every matching subscriber without ever waiting: a
+/// subscriber whose buffer is full is dropped, ending its stream.
+pub fn publish(
+    kind: Kind,
+    correlation_id: Uuid,
+    processor: Option<&UpstreamType>,
+    attempt: Option<u32>,
+) {
+    if SUBSCRIBER_COUNT.load(atomic::Ordering::Relaxed) == 0 {
+        return;
+    }
+
+    let event = Event {
+        kind: kind.as_str(),
+        correlation_id,
+        processor,
+        attempt,
+        at: Utc::now(),
+    };
+    let Ok(data) = serde_json::to_string(&event) else {
+        return;
+    };
+    let frame = Bytes::from(format!("event: {}\ndata: {data}\n\n", kind.as_str()));
+
+    let mut subscribers = SUBSCRIBERS.lock().unwrap_or_else(PoisonError::into_inner);
+    subscribers.retain(|subscriber| {
+        !subscriber.filter.matches(kind) || subscriber.sender.try_send(frame.clone()).is_ok()
+    });
+    SUBSCRIBER_COUNT.store(subscribers.len(), atomic::Ordering::Relaxed);
+}
+
+pub fn subscribe(filter: Filter) -> EventBody {
+    let (sender, receiver) = mpsc::channel(SUBSCRIBER_BUFFER);
+
+    let mut subscribers = SUBSCRIBERS.lock().unwrap_or_else(PoisonError::into_inner);
+    subscribers.push(Subscriber { filter, sender });
+    SUBSCRIBER_COUNT.store(subscribers.len(), atomic::Ordering::Relaxed);
+
+    let mut heartbeat = interval(HEARTBEAT);
+    heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    EventBody {
+        receiver,
+        heartbeat,
+    }
+}
+
+/// Response body of one subscriber, ending once `publish` drops it. Idle
+/// streams get a comment line every `HEARTBEAT` so proxies keep them open.
+#[derive(Debug)]
+pub struct EventBody {
+    receiver: mpsc::Receiver<Bytes>,
+    heartbeat: Interval,
+}
+
+impl Body for EventBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        if let Poll::Ready(frame) = self.receiver.poll_recv(cx) {
+            return Poll::Ready(frame.map(|frame| Ok(Frame::data(frame))));
+        }
+
+        if self.heartbeat.poll_tick(cx).is_ready() {
+            return Poll::Ready(Some(Ok(Frame::data(Bytes::from_static(
+                b": heartbeat\n\n",
+            )))));
+        }
+
+        Poll::Pending
+    }
+}
+
+pub fn bootstrap() {
+    LazyLock::force(&SUBSCRIBERS);
+}
@@ -0,0 +1,125 @@
+use crate::{
+    rinha_conf,
+    rinha_net::{self, JSON_CONTENT_TYPE},
+};
+use http::{Method, Request, header};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use serde::de::DeserializeOwned;
+use std::ops::AddAssign;
+use tokio::{
+    task::JoinSet,
+    time::{Duration, timeout},
+};
+
+/// Set on every fan-out request; a summary request carrying it is answered
+/// from local storage only, so misconfigured peers can never fan out again.
+pub const FORWARDED_HEADER: &str = "x-rinha-forwarded";
+
+/// Set on federated responses to the number of peers left out of the totals.
+pub const UNREACHABLE_HEADER: &str = "x-rinha-unreachable-peers";
+
+pub const INTERNAL_SUMMARY_PATH: &str = "/internal/payments-summary";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerPolicy {
+    /// answer with the totals of the reachable peers and flag the rest
+    Skip,
+    /// refuse to answer unless every peer contributed
+    Fail,
+}
+
+impl PeerPolicy {
+    pub fn parse(policy: &str) -> Self {
+        match policy {
+            "fail" => Self::Fail,
+            _ => Self::Skip,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum FetchError {
+    #[error("http")]
+    HTTP(#[from] http::Error),
+    #[error("hyper")]
+    Hyper(#[from] hyper::Error),
+    #[error("client")]
+    Client(#[from] hyper_util::client::legacy::Error),
+    #[error("serde")]
+    Serde(#[from] serde_json::Error),
+
+    #[error("status {0}")]
+    Status(http::StatusCode),
+    #[error("timeout")]
+    Timeout,
+}
+
+async fn fetch<T: DeserializeOwned>(peer: &str, query: &str) -> Result<T, FetchError> {
+    let client = rinha_net::get_client();
+    let uri = format!("http://{peer}{INTERNAL_SUMMARY_PATH}?{query}");
+    let res = client
+        .request(
+            Request::builder()
+                .method(Method::GET)
+                .uri(uri)
+                .header(header::ACCEPT, JSON_CONTENT_TYPE)
+                .header(FORWARDED_HEADER, "1")
+                .body(Full::new(Bytes::new()))?,
+        )
+        .await?;
+
+    if !res.status().is_success() {
+        return Err(FetchError::Status(res.status()));
+    }
+
+    let body = res.into_body().collect().await?.to_bytes();
+
+    Ok(serde_json::from_slice(&body)?)
+}
+
+#[derive(Debug, Default)]
+pub struct Federated<T> {
+    pub summary: T,
+    pub unreachable: usize,
+}
+
+/// Fetches the local-only totals of every `RINHA_PEERS` entry concurrently,
+/// each bounded by `RINHA_PEER_TIMEOUT_MS`, forwarding the original query,
+/// so they come back in whichever shape that query asks for.
+pub async fn summary<T>(query: &str) -> Federated<T>
+where
+    T: DeserializeOwned + Default + AddAssign + Send + 'static,
+{
+    let mut tasks = JoinSet::new();
+    let wait = Duration::from_millis(*rinha_conf::RINHA_PEER_TIMEOUT_MS);
+
+    for peer in rinha_conf::RINHA_PEERS.iter() {
+        let query = query.to_owned();
+
+        tasks.spawn(async move {
+            let res = timeout(wait, fetch::<T>(peer, &query))
+                .await
+                .unwrap_or(Err(FetchError::Timeout));
+            (peer, res)
+        });
+    }
+
+    let mut federated = Federated::<T>::default();
+
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok((_, Ok(summary))) => federated.summary += summary,
+            Ok((peer, Err(err))) => {
+                tracing::warn!(?err, peer, "peer summary");
+                federated.unreachable += 1;
+            }
+            Err(err) => {
+                tracing::error!(?err, "peer summary");
+                federated.unreachable += 1;
+            }
+        }
+    }
+
+    federated
+}
@@ -1,16 +1,48 @@
 use crate::{
-    rinha_chan,
-    rinha_domain::{Payment, TargetCounter, dt_to_i64},
-    rinha_net::JSON_CONTENT_TYPE,
-    rinha_storage,
+    rinha_ambulance, rinha_chan, rinha_conf, rinha_dedup,
+    rinha_domain::{Payment, Series, SeriesBucket, TargetCounter, dt_to_i64},
+    rinha_events::{self, EventBody, Kind},
+    rinha_federation, rinha_metrics,
+    rinha_net::{self, JSON_CONTENT_TYPE},
+    rinha_status, rinha_storage,
 };
-use chrono::{DateTime, TimeZone, Utc};
-use http_body_util::{BodyExt, Full};
+use chrono::DateTime;
+use http_body_util::{BodyExt, Full, Limited};
 use hyper::{
     Request, Response, StatusCode,
     body::{Bytes, Incoming},
-    header,
+    header::{self, HeaderValue},
 };
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{error::Category, value::RawValue};
+use std::ops::AddAssign;
+use tokio::sync::mpsc::error::TrySendError;
+use uuid::Uuid;
+
+mod query;
+
+pub use query::QueryError;
+use query::{Buckets, SummaryQuery, parse_event_filter};
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+}
+
+/// `{"error": message}` with `status`; built without `Builder` so it cannot
+/// fail itself.
+pub fn error_response(status: StatusCode, message: &str) -> Response<Full<Bytes>> {
+    let body = serde_json::to_vec(&ErrorBody { error: message }).unwrap_or_default();
+    let mut res = Response::new(Full::new(body.into()));
+
+    *res.status_mut() = status;
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(JSON_CONTENT_TYPE),
+    );
+
+    res
+}
 
 #[derive(thiserror::Error, Debug)]
 pub enum PaymentsError {
@@ -26,17 +58,365 @@ pub enum PaymentsError {
     TrySend(#[from] rinha_chan::PaymentTrySendError),
 }
 
+impl PaymentsError {
+    /// Malformed JSON is a 400, well-formed JSON that is not a valid payment
+    /// a 422, and a full queue a 503.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Hyper(_) => StatusCode::BAD_REQUEST,
+            Self::Serde(err) => match err.classify() {
+                Category::Data => StatusCode::UNPROCESSABLE_ENTITY,
+                Category::Syntax | Category::Eof | Category::Io => StatusCode::BAD_REQUEST,
+            },
+            Self::TrySend(TrySendError::Full(_)) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::TrySend(TrySendError::Closed(_)) | Self::Send(_) | Self::HTTP(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
+const EXPIRED_MESSAGE: &str = "requestedAt before the roll-up horizon";
+
+enum Rejection {
+    Duplicate,
+    /// the ledger has no room left, so the payment could never be recorded
+    StorageFull,
+    /// requested before the roll-up horizon
+    Expired,
+    Unsent(rinha_chan::PaymentTrySendError),
+}
+
+impl Rejection {
+    fn reason(&self) -> rinha_metrics::Rejected {
+        match self {
+            Self::Duplicate => rinha_metrics::Rejected::Duplicate,
+            Self::StorageFull => rinha_metrics::Rejected::StorageFull,
+            Self::Expired => rinha_metrics::Rejected::Expired,
+            Self::Unsent(TrySendError::Full(_)) => rinha_metrics::Rejected::QueueFull,
+            Self::Unsent(TrySendError::Closed(_)) => rinha_metrics::Rejected::Closed,
+        }
+    }
+}
+
+/// Admits and enqueues one payment, counting every refusal by its reason.
+fn enqueue(payment: Payment) -> Result<(), Rejection> {
+    try_enqueue(payment).inspect_err(|rejection| rinha_metrics::rejected(rejection.reason()))
+}
+
+/// Undoes the admission when no channel takes the payment.
+fn try_enqueue(payment: Payment) -> Result<(), Rejection> {
+    let correlation_id = payment.correlation_id;
+
+    if rinha_storage::get_storage().is_full() {
+        return Err(Rejection::StorageFull);
+    }
+    if dt_to_i64(payment.requested_at) < rinha_storage::horizon() {
+        return Err(Rejection::Expired);
+    }
+
+    if !rinha_dedup::admit(correlation_id) {
+        return Err(Rejection::Duplicate);
+    }
+
+    rinha_status::queued(correlation_id);
+
+    if let Err(err) = rinha_chan::try_send(rinha_chan::Queued::new(payment)) {
+        rinha_dedup::forget(correlation_id);
+        rinha_status::forget(correlation_id);
+        return Err(Rejection::Unsent(err));
+    }
+
+    rinha_metrics::accepted();
+    rinha_events::publish(Kind::Accepted, correlation_id, None, None);
+
+    Ok(())
+}
+
+fn with_retry_after(mut res: Response<Full<Bytes>>) -> Response<Full<Bytes>> {
+    res.headers_mut().insert(
+        header::RETRY_AFTER,
+        HeaderValue::from(rinha_chan::retry_after().as_secs()),
+    );
+    res
+}
+
 pub async fn payments(req: Request<Incoming>) -> Result<Response<Full<Bytes>>, PaymentsError> {
     let body = req.into_body().collect().await?.to_bytes();
-    let payment = serde_json::from_slice::<Payment>(&body)?;
-    let sender = rinha_chan::get_sender();
-    sender.try_send(payment)?;
+    let payment =
+        serde_json::from_slice::<Payment>(&body).inspect_err(|_| rinha_metrics::invalid())?;
+
+    match enqueue(payment) {
+        Ok(()) => {}
+        Err(Rejection::Duplicate) => {
+            return Ok(error_response(
+                StatusCode::CONFLICT,
+                "duplicate correlation id",
+            ));
+        }
+        Err(Rejection::StorageFull) => {
+            return Ok(error_response(
+                StatusCode::INSUFFICIENT_STORAGE,
+                "storage full",
+            ));
+        }
+        Err(Rejection::Expired) => {
+            return Ok(error_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                EXPIRED_MESSAGE,
+            ));
+        }
+        Err(Rejection::Unsent(TrySendError::Full(_))) => {
+            return Ok(with_retry_after(error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "queues full",
+            )));
+        }
+        Err(Rejection::Unsent(err)) => return Err(err.into()),
+    }
 
     Ok(Response::builder()
         .status(StatusCode::OK)
         .body(Full::new(Bytes::new()))?)
 }
 
+pub const PAYMENTS_BATCH_PATH: &str = "/payments/batch";
+
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Most payments a single batch may carry.
+const MAX_BATCH: usize = 10_000;
+/// Most bytes a single batch may take, read before it is counted; room for
+/// `MAX_BATCH` payments with some whitespace to spare.
+pub const MAX_BATCH_BYTES: usize = MAX_BATCH * 256;
+
+#[derive(thiserror::Error, Debug)]
+pub enum PaymentsBatchError {
+    #[error("hyper")]
+    Hyper(#[from] hyper::Error),
+    #[error("serde")]
+    Serde(#[from] serde_json::Error),
+    #[error("http")]
+    HTTP(#[from] http::Error),
+    #[error("utf8")]
+    Utf8(#[from] std::str::Utf8Error),
+
+    #[error("more than {0} payments")]
+    TooLarge(usize),
+    #[error("more than {0} bytes")]
+    BodyTooLarge(usize),
+}
+
+impl PaymentsBatchError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Hyper(_) | Self::Serde(_) | Self::Utf8(_) => StatusCode::BAD_REQUEST,
+            Self::TooLarge(_) | Self::BodyTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::HTTP(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BatchItem {
+    #[serde(rename = "index")]
+    index: usize,
+    #[serde(rename = "correlationId", skip_serializing_if = "Option::is_none")]
+    correlation_id: Option<Uuid>,
+    #[serde(rename = "status")]
+    status: u16,
+    #[serde(rename = "error", skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize, Default)]
+struct BatchResult {
+    #[serde(rename = "accepted")]
+    accepted: usize,
+    #[serde(rename = "rejected")]
+    rejected: usize,
+    #[serde(rename = "results")]
+    results: Vec<BatchItem>,
+}
+
+impl BatchResult {
+    fn push(&mut self, index: usize, item: &str) {
+        let (correlation_id, res) = match serde_json::from_str::<Payment>(item) {
+            Ok(payment) => (Some(payment.correlation_id), enqueue(payment)),
+            Err(err) => {
+                rinha_metrics::invalid();
+                let status = match err.classify() {
+                    Category::Data => StatusCode::UNPROCESSABLE_ENTITY,
+                    Category::Syntax | Category::Eof | Category::Io => StatusCode::BAD_REQUEST,
+                };
+                return self.reject(index, None, status, err.to_string());
+            }
+        };
+
+        match res {
+            Ok(()) => {
+                self.accepted += 1;
+                self.results.push(BatchItem {
+                    index,
+                    correlation_id,
+                    status: StatusCode::OK.as_u16(),
+                    error: None,
+                });
+            }
+            Err(Rejection::Duplicate) => self.reject(
+                index,
+                correlation_id,
+                StatusCode::CONFLICT,
+                "duplicate correlation id".into(),
+            ),
+            Err(Rejection::StorageFull) => self.reject(
+                index,
+                correlation_id,
+                StatusCode::INSUFFICIENT_STORAGE,
+                "storage full".into(),
+            ),
+            Err(Rejection::Expired) => self.reject(
+                index,
+                correlation_id,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                EXPIRED_MESSAGE.into(),
+            ),
+            Err(Rejection::Unsent(TrySendError::Full(_))) => self.reject(
+                index,
+                correlation_id,
+                StatusCode::SERVICE_UNAVAILABLE,
+                "queues full".into(),
+            ),
+            Err(Rejection::Unsent(TrySendError::Closed(_))) => self.reject(
+                index,
+                correlation_id,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "queues closed".into(),
+            ),
+        }
+    }
+
+    fn reject(
+        &mut self,
+        index: usize,
+        correlation_id: Option<Uuid>,
+        status: StatusCode,
+        error: String,
+    ) {
+        self.rejected += 1;
+        self.results.push(BatchItem {
+            index,
+            correlation_id,
+            status: status.as_u16(),
+            error: Some(error),
+        });
+    }
+}
+
+/// Takes a JSON array of payments, or one payment per line when sent as
+/// `application/x-ndjson` or not starting with `[`, and answers with the
+/// outcome of every item in order; one bad item never fails the others.
+pub async fn payments_batch(
+    req: Request<Incoming>,
+) -> Result<Response<Full<Bytes>>, PaymentsBatchError> {
+    let ndjson = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| {
+            content_type
+                .as_bytes()
+                .starts_with(NDJSON_CONTENT_TYPE.as_bytes())
+        });
+    // `Limited` fails with either the body's own error or the limit's
+    let body = Limited::new(req.into_body(), MAX_BATCH_BYTES)
+        .collect()
+        .await
+        .map_err(|err| match err.downcast::<hyper::Error>() {
+            Ok(err) => PaymentsBatchError::Hyper(*err),
+            Err(_) => PaymentsBatchError::BodyTooLarge(MAX_BATCH_BYTES),
+        })?
+        .to_bytes();
+    let text = std::str::from_utf8(&body)?;
+
+    let items: Vec<&str> = if !ndjson && text.trim_start().starts_with('[') {
+        serde_json::from_str::<Vec<&RawValue>>(text)?
+            .into_iter()
+            .map(RawValue::get)
+            .collect()
+    } else {
+        text.lines()
+            .filter(|line| !line.trim().is_empty())
+            .collect()
+    };
+
+    if items.len() > MAX_BATCH {
+        return Err(PaymentsBatchError::TooLarge(MAX_BATCH));
+    }
+
+    let mut result = BatchResult::default();
+    for (index, item) in items.into_iter().enumerate() {
+        result.push(index, item);
+    }
+
+    let full = result
+        .results
+        .iter()
+        .any(|item| item.status == StatusCode::SERVICE_UNAVAILABLE.as_u16());
+    let body = serde_json::to_vec(&result)?;
+    let mut res = Response::builder()
+        .header(header::CONTENT_TYPE, JSON_CONTENT_TYPE)
+        .status(StatusCode::OK)
+        .body(Full::new(body.into()))?;
+
+    if full {
+        res = with_retry_after(res);
+    }
+
+    Ok(res)
+}
+
+pub const PAYMENT_STATUS_PREFIX: &str = "/payments/";
+
+#[derive(thiserror::Error, Debug)]
+pub enum PaymentStatusError {
+    #[error("serde")]
+    Serde(#[from] serde_json::Error),
+    #[error("http")]
+    HTTP(#[from] http::Error),
+}
+
+/// Where the payment behind `/payments/{correlationId}` currently stands.
+pub async fn payment_status(
+    req: Request<Incoming>,
+) -> Result<Response<Full<Bytes>>, PaymentStatusError> {
+    let id = req
+        .uri()
+        .path()
+        .strip_prefix(PAYMENT_STATUS_PREFIX)
+        .and_then(|id| Uuid::parse_str(id).ok());
+
+    let Some(id) = id else {
+        return Ok(error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid correlation id",
+        ));
+    };
+
+    let Some(status) = rinha_status::get(&id) else {
+        return Ok(error_response(
+            StatusCode::NOT_FOUND,
+            "unknown correlation id",
+        ));
+    };
+
+    let body = serde_json::to_vec(&status)?;
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, JSON_CONTENT_TYPE)
+        .status(StatusCode::OK)
+        .body(Full::new(body.into()))?)
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum PaymentsSummaryError {
     #[error("serde")]
@@ -45,57 +425,165 @@ pub enum PaymentsSummaryError {
     HTTP(#[from] http::Error),
     #[error("hyper")]
     Hyper(#[from] hyper::Error),
+    #[error("query")]
+    Query(#[from] QueryError),
+}
 
-    #[error("infallible")]
-    Infallible,
+impl PaymentsSummaryError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Query(_) => StatusCode::BAD_REQUEST,
+            Self::Serde(_) | Self::HTTP(_) | Self::Hyper(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+fn local_summary(query: &SummaryQuery) -> TargetCounter {
+    rinha_storage::get_storage().summary(query.from, query.to)
 }
 
+fn local_series(buckets: &Buckets) -> Series {
+    let counters = rinha_storage::get_storage().series(&buckets.bounds);
+
+    Series(
+        counters
+            .into_iter()
+            .zip(buckets.edges.windows(2))
+            .map(|(counter, edges)| SeriesBucket {
+                start: edges[0].fixed_offset(),
+                end: edges[1].fixed_offset(),
+                default: counter.default,
+                fallback: counter.fallback,
+            })
+            .collect(),
+    )
+}
+
+/// Set on summaries whose window starts before the roll-up horizon: totals
+/// before that instant count per `RINHA_ROLLUP_BUCKET_SECS` bucket, whole
+/// whenever the bucket starts within the window.
+pub const ROLLED_UP_BEFORE_HEADER: &str = "x-rinha-rolled-up-before";
+
+/// Local totals merged with those of every `RINHA_PEERS` instance, unless the
+/// request was itself fanned out by a peer. With an `interval` they are
+/// broken down into a time series instead.
 pub async fn payments_summary(
     req: Request<Incoming>,
 ) -> Result<Response<Full<Bytes>>, PaymentsSummaryError> {
-    let mut from = Utc
-        .timestamp_opt(0, 0)
-        .single()
-        .ok_or_else(|| PaymentsSummaryError::Infallible)?;
-    let mut to = Utc::now();
-
-    if let Some(query) = req.uri().query() {
-        for param in query.split("&") {
-            if let Some(dt) = param.strip_prefix("from=") {
-                if let Ok(dt) = DateTime::parse_from_rfc3339(dt).map(|dt| dt.with_timezone(&Utc)) {
-                    from = dt;
-                }
-            } else if let Some(dt) = param.strip_prefix("to=") {
-                if let Ok(dt) = DateTime::parse_from_rfc3339(dt).map(|dt| dt.with_timezone(&Utc)) {
-                    to = dt;
-                }
-            }
-        }
+    let query = SummaryQuery::parse(req.uri().query())?;
+
+    let mut res = match query.buckets()? {
+        Some(buckets) => federate(&req, local_series(&buckets)).await?,
+        None => federate(&req, local_summary(&query)).await?,
     };
 
-    let (default_storage, fallback_storage) = (
-        rinha_storage::get_default_storage(),
-        rinha_storage::get_fallback_storage(),
-    );
-    let (default_storage, fallback_storage) =
-        tokio::join!(default_storage.read(), fallback_storage.read());
+    let horizon = rinha_storage::horizon();
+    if query.from < horizon
+        && let Some(horizon) = DateTime::from_timestamp_micros(horizon)
+        && let Ok(value) = HeaderValue::from_str(&horizon.to_rfc3339())
+    {
+        res.headers_mut().insert(ROLLED_UP_BEFORE_HEADER, value);
+    }
 
-    let from = dt_to_i64(from);
-    let to = dt_to_i64(to);
+    Ok(res)
+}
 
-    let mut target_counter = TargetCounter::default();
+async fn federate<T>(
+    req: &Request<Incoming>,
+    mut summary: T,
+) -> Result<Response<Full<Bytes>>, PaymentsSummaryError>
+where
+    T: Serialize + DeserializeOwned + Default + AddAssign + Send + 'static,
+{
+    let mut res = Response::builder().header(header::CONTENT_TYPE, JSON_CONTENT_TYPE);
 
-    for (_, amount) in default_storage.range(from..=to) {
-        target_counter.default.requests += 1;
-        target_counter.default.amount += amount;
-    }
+    let forwarded = req
+        .headers()
+        .contains_key(rinha_federation::FORWARDED_HEADER);
 
-    for (_, amount) in fallback_storage.range(from..=to) {
-        target_counter.fallback.requests += 1;
-        target_counter.fallback.amount += amount;
+    if !forwarded && !rinha_conf::RINHA_PEERS.is_empty() {
+        let query = req.uri().query().unwrap_or_default();
+        let federated = rinha_federation::summary::<T>(query).await;
+        let policy = rinha_federation::PeerPolicy::parse(rinha_conf::RINHA_PEER_POLICY.as_str());
+
+        if federated.unreachable > 0 && policy == rinha_federation::PeerPolicy::Fail {
+            let mut res = error_response(StatusCode::BAD_GATEWAY, "peer unreachable");
+            res.headers_mut().insert(
+                rinha_federation::UNREACHABLE_HEADER,
+                HeaderValue::from(federated.unreachable),
+            );
+
+            return Ok(res);
+        }
+
+        summary += federated.summary;
+        res = res.header(rinha_federation::UNREACHABLE_HEADER, federated.unreachable);
     }
 
-    let body = serde_json::to_vec(&target_counter)?;
+    let body = serde_json::to_vec(&summary)?;
+
+    Ok(res.status(StatusCode::OK).body(Full::new(body.into()))?)
+}
+
+/// Totals of this instance alone, fetched by peers fanning out a summary.
+pub async fn internal_payments_summary(
+    req: Request<Incoming>,
+) -> Result<Response<Full<Bytes>>, PaymentsSummaryError> {
+    let query = SummaryQuery::parse(req.uri().query())?;
+    let body = match query.buckets()? {
+        Some(buckets) => serde_json::to_vec(&local_series(&buckets))?,
+        None => serde_json::to_vec(&local_summary(&query))?,
+    };
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, JSON_CONTENT_TYPE)
+        .status(StatusCode::OK)
+        .body(Full::new(body.into()))?)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum PurgePaymentsError {
+    #[error("http")]
+    HTTP(#[from] http::Error),
+    #[error("storage")]
+    Storage(#[from] rinha_storage::StorageError),
+    #[error("join")]
+    Join(#[from] tokio::task::JoinError),
+}
+
+/// Discards queued and in-flight payments, forgets every admitted
+/// correlation id and empties both ledgers, answering only once all of it
+/// is done and persisted.
+pub async fn purge_payments() -> Result<Response<Full<Bytes>>, PurgePaymentsError> {
+    tokio::task::spawn_blocking(|| {
+        rinha_chan::advance(|| {
+            rinha_dedup::reset();
+            rinha_status::reset();
+            rinha_storage::purge()
+        })?;
+
+        // recording has resumed by now, the disk work holds nothing back
+        rinha_storage::persist_purge()
+    })
+    .await??;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .body(Full::new(Bytes::new()))?)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum StorageFootprintError {
+    #[error("serde")]
+    Serde(#[from] serde_json::Error),
+    #[error("http")]
+    HTTP(#[from] http::Error),
+}
+
+/// Estimated memory held by this instance's ledgers, against its budget.
+pub async fn storage_footprint() -> Result<Response<Full<Bytes>>, StorageFootprintError> {
+    let footprint = rinha_storage::get_storage().footprint();
+    let body = serde_json::to_vec(&footprint)?;
 
     Ok(Response::builder()
         .header(header::CONTENT_TYPE, JSON_CONTENT_TYPE)
@@ -103,6 +591,145 @@ pub async fn payments_summary(
         .body(Full::new(body.into()))?)
 }
 
+#[derive(thiserror::Error, Debug)]
+pub enum QueueStatsError {
+    #[error("serde")]
+    Serde(#[from] serde_json::Error),
+    #[error("http")]
+    HTTP(#[from] http::Error),
+}
+
+/// Depth, drain rate and rejections of the ingestion queues, for sizing them.
+pub async fn queue_stats() -> Result<Response<Full<Bytes>>, QueueStatsError> {
+    let body = serde_json::to_vec(&rinha_chan::stats())?;
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, JSON_CONTENT_TYPE)
+        .status(StatusCode::OK)
+        .body(Full::new(body.into()))?)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum MetricsError {
+    #[error("http")]
+    HTTP(#[from] http::Error),
+}
+
+/// Counters, queue depths, upstream latency and health, for Prometheus.
+pub async fn metrics() -> Result<Response<Full<Bytes>>, MetricsError> {
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, rinha_metrics::METRICS_CONTENT_TYPE)
+        .status(StatusCode::OK)
+        .body(Full::new(rinha_metrics::render().into()))?)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum EventsError {
+    #[error("http")]
+    HTTP(#[from] http::Error),
+    #[error("query")]
+    Query(#[from] QueryError),
+}
+
+impl EventsError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Query(_) => StatusCode::BAD_REQUEST,
+            Self::HTTP(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Server-sent stream of payment lifecycle events, optionally narrowed down
+/// with `type`.
+pub async fn events(req: Request<Incoming>) -> Result<Response<EventBody>, EventsError> {
+    let filter = parse_event_filter(req.uri().query())?;
+
+    Ok(Response::builder()
+        .header(
+            header::CONTENT_TYPE,
+            rinha_events::EVENT_STREAM_CONTENT_TYPE,
+        )
+        .header(header::CACHE_CONTROL, "no-cache")
+        .status(StatusCode::OK)
+        .body(rinha_events::subscribe(filter))?)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProbeError {
+    #[error("serde")]
+    Serde(#[from] serde_json::Error),
+    #[error("http")]
+    HTTP(#[from] http::Error),
+}
+
+fn probe_response(ok: bool, detail: &impl Serialize) -> Result<Response<Full<Bytes>>, ProbeError> {
+    let body = serde_json::to_vec(detail)?;
+    let status = if ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, JSON_CONTENT_TYPE)
+        .status(status)
+        .body(Full::new(body.into()))?)
+}
+
+#[derive(Serialize)]
+struct Liveness {
+    #[serde(rename = "alive")]
+    alive: bool,
+    #[serde(rename = "accepting")]
+    accepting: bool,
+}
+
+/// Liveness: answering at all means the runtime is not wedged, and the
+/// accept loop must still be running.
+pub async fn healthz() -> Result<Response<Full<Bytes>>, ProbeError> {
+    let accepting = rinha_net::is_accepting();
+
+    probe_response(
+        accepting,
+        &Liveness {
+            alive: true,
+            accepting,
+        },
+    )
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    #[serde(rename = "ready")]
+    ready: bool,
+    #[serde(rename = "upstreams")]
+    upstreams: rinha_ambulance::UpstreamsHealth,
+    #[serde(rename = "queues")]
+    queues: rinha_chan::QueueStats,
+    #[serde(rename = "queueThreshold")]
+    queue_threshold: usize,
+}
+
+/// Readiness: upstreams resolved, at least one of them healthy, and the
+/// queues below `RINHA_READY_QUEUE_PERCENT` of their capacity.
+pub async fn readyz() -> Result<Response<Full<Bytes>>, ProbeError> {
+    let upstreams = rinha_ambulance::health();
+    let queues = rinha_chan::stats();
+    let queue_threshold = queues.capacity * *rinha_conf::RINHA_READY_QUEUE_PERCENT as usize / 100;
+    let ready = upstreams.resolved && upstreams.any_healthy() && queues.depth < queue_threshold;
+
+    probe_response(
+        ready,
+        &Readiness {
+            ready,
+            upstreams,
+            queues,
+            queue_threshold,
+        },
+    )
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum NotFoundError {
     #[error("http")]
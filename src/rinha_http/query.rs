@@ -0,0 +1,401 @@
+use crate::{
+    rinha_domain::dt_to_i64,
+    rinha_events::{Filter, Kind},
+};
+use chrono::{DateTime, Days, LocalResult, NaiveDate, NaiveDateTime, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
+use percent_encoding::percent_decode_str;
+
+/// Upper bound on the buckets of one time series.
+const MAX_BUCKETS: usize = 10_000;
+/// Buckets of a time series whose `from` is not given.
+const DEFAULT_BUCKETS: i32 = 60;
+
+#[derive(thiserror::Error, Debug)]
+pub enum QueryError {
+    #[error("`{0}` is not valid utf-8 once decoded")]
+    Decode(String),
+    #[error("invalid `{param}`: {value}")]
+    Invalid { param: &'static str, value: String },
+    #[error("`{0}` given more than once")]
+    Duplicate(&'static str),
+    #[error("`from` is after `to`")]
+    Inverted,
+    #[error("more than {0} buckets")]
+    TooManyBuckets(usize),
+    #[error("buckets end past the representable range")]
+    OutOfRange,
+}
+
+/// Window of a summary, in microseconds. It holds every payment with
+/// `from <= requestedAt <= to`: both ends are inclusive, so adjacent windows
+/// must not share an instant. A missing `to` is the current time, and a
+/// missing `from` the Unix epoch, or `DEFAULT_BUCKETS` intervals before `to`
+/// when an `interval` is given.
+///
+/// With an `interval` the window is broken down into buckets aligned on
+/// multiples of it since 1970-01-01 on the wall clock of `tz` (UTC unless
+/// given), so `1d` buckets follow local midnights across DST changes.
+#[derive(Debug, Clone, Copy)]
+pub struct SummaryQuery {
+    pub from: i64,
+    pub to: i64,
+    pub interval: Option<TimeDelta>,
+    pub tz: Tz,
+}
+
+/// Edges of the buckets of a time series: bucket `i` reports
+/// `edges[i]..edges[i + 1]` and sums the payments within
+/// `bounds[i]..bounds[i + 1]`, the same edges clipped to the window.
+#[derive(Debug)]
+pub struct Buckets {
+    pub edges: Vec<DateTime<Tz>>,
+    pub bounds: Vec<i64>,
+}
+
+/// `<n>s`, `<n>m`, `<n>h` or `<n>d`, `n` at least 1.
+fn parse_interval(value: &str) -> Result<TimeDelta, QueryError> {
+    let invalid = || QueryError::Invalid {
+        param: "interval",
+        value: value.into(),
+    };
+
+    let split = value.len().checked_sub(1).ok_or_else(invalid)?;
+    let (n, unit) = value.split_at_checked(split).ok_or_else(invalid)?;
+    let n = n
+        .parse::<i64>()
+        .ok()
+        .filter(|n| *n > 0)
+        .ok_or_else(invalid)?;
+
+    match unit {
+        "s" => TimeDelta::try_seconds(n),
+        "m" => TimeDelta::try_minutes(n),
+        "h" => TimeDelta::try_hours(n),
+        "d" => TimeDelta::try_days(n),
+        _ => None,
+    }
+    .ok_or_else(invalid)
+}
+
+/// Percent-decodes without turning `+` into a space, so unencoded `+00:00`
+/// offsets survive.
+fn decode(raw: &str) -> Result<String, QueryError> {
+    percent_decode_str(raw)
+        .decode_utf8()
+        .map(|decoded| decoded.into_owned())
+        .map_err(|_| QueryError::Decode(raw.into()))
+}
+
+/// RFC 3339, milliseconds since the epoch, or a bare `YYYY-MM-DD` date in
+/// UTC. A date covers its whole day: as `from` it starts at midnight, as
+/// `to` it ends right before the next one.
+fn parse_instant(param: &'static str, value: &str) -> Result<DateTime<Utc>, QueryError> {
+    let invalid = || QueryError::Invalid {
+        param,
+        value: value.into(),
+    };
+
+    if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+        return Ok(dt.with_timezone(&Utc));
+    }
+
+    if let Ok(millis) = value.parse::<i64>() {
+        return Utc
+            .timestamp_millis_opt(millis)
+            .single()
+            .ok_or_else(invalid);
+    }
+
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| invalid())?;
+    let midnight = match param {
+        "to" => date.checked_add_days(Days::new(1)).ok_or_else(invalid)?,
+        _ => date,
+    };
+    let dt = midnight.and_hms_opt(0, 0, 0).ok_or_else(invalid)?.and_utc();
+
+    Ok(match param {
+        "to" => dt - chrono::Duration::microseconds(1),
+        _ => dt,
+    })
+}
+
+impl SummaryQuery {
+    /// Unknown parameters are ignored; a malformed, repeated or inverted
+    /// `from`/`to` is an error rather than a silent fallback.
+    pub fn parse(query: Option<&str>) -> Result<Self, QueryError> {
+        let mut from = None;
+        let mut to = None;
+        let mut interval = None;
+        let mut tz = None;
+
+        for pair in query.unwrap_or_default().split('&') {
+            if pair.is_empty() {
+                continue;
+            }
+
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = decode(value)?;
+
+            match decode(key)?.as_str() {
+                "from" => set(&mut from, "from", parse_instant("from", &value)?)?,
+                "to" => set(&mut to, "to", parse_instant("to", &value)?)?,
+                "interval" => set(&mut interval, "interval", parse_interval(&value)?)?,
+                "tz" => set(
+                    &mut tz,
+                    "tz",
+                    value.parse::<Tz>().map_err(|_| QueryError::Invalid {
+                        param: "tz",
+                        value: value.clone(),
+                    })?,
+                )?,
+                _ => continue,
+            }
+        }
+
+        let to = to.unwrap_or_else(Utc::now);
+        let from = from.unwrap_or_else(|| {
+            interval
+                .and_then(|interval| interval.checked_mul(DEFAULT_BUCKETS))
+                .and_then(|window| to.checked_sub_signed(window))
+                .unwrap_or(DateTime::<Utc>::UNIX_EPOCH)
+        });
+
+        if from > to {
+            return Err(QueryError::Inverted);
+        }
+
+        Ok(Self {
+            from: dt_to_i64(from),
+            to: dt_to_i64(to),
+            interval,
+            tz: tz.unwrap_or(Tz::UTC),
+        })
+    }
+
+    /// `None` unless an `interval` was asked for.
+    pub fn buckets(&self) -> Result<Option<Buckets>, QueryError> {
+        let Some(interval) = self.interval else {
+            return Ok(None);
+        };
+
+        // refused before a single edge is computed; an interval longer than
+        // the window leaves at most two edges, and only the last of them may
+        // land past what a `DateTime` holds
+        let span = self.to.saturating_sub(self.from);
+        let width = interval.num_microseconds().unwrap_or(i64::MAX);
+        if span / width > MAX_BUCKETS as i64 {
+            return Err(QueryError::TooManyBuckets(MAX_BUCKETS));
+        }
+
+        let from = DateTime::from_timestamp_micros(self.from)
+            .unwrap_or_default()
+            .with_timezone(&self.tz);
+        let step = interval.num_seconds();
+        let secs = from.naive_local().and_utc().timestamp();
+        let mut wall = DateTime::from_timestamp(secs - secs.rem_euclid(step), 0)
+            .unwrap_or_default()
+            .naive_utc();
+
+        let mut edges = vec![self.at_wall_clock(wall)?];
+        while edges
+            .last()
+            .is_some_and(|edge| dt_to_i64(edge.to_utc()) <= self.to)
+        {
+            if edges.len() > MAX_BUCKETS {
+                return Err(QueryError::TooManyBuckets(MAX_BUCKETS));
+            }
+
+            wall = wall
+                .checked_add_signed(interval)
+                .ok_or(QueryError::OutOfRange)?;
+            let edge = self.at_wall_clock(wall)?;
+            // a wall clock turned back may repeat an edge already passed
+            if edges.last().is_some_and(|last| edge > *last) {
+                edges.push(edge);
+            }
+        }
+
+        let mut bounds: Vec<i64> = edges.iter().map(|edge| dt_to_i64(edge.to_utc())).collect();
+        if let Some(first) = bounds.first_mut() {
+            *first = self.from;
+        }
+        if let Some(last) = bounds.last_mut() {
+            *last = self.to + 1;
+        }
+
+        Ok(Some(Buckets { edges, bounds }))
+    }
+
+    /// The instant `wall` reads on the clocks of `tz`: the earlier one when
+    /// it reads twice, and the end of the gap when it never does.
+    fn at_wall_clock(&self, wall: NaiveDateTime) -> Result<DateTime<Tz>, QueryError> {
+        match self.tz.from_local_datetime(&wall) {
+            LocalResult::Single(dt) | LocalResult::Ambiguous(dt, _) => Ok(dt),
+            LocalResult::None => {
+                let later = wall
+                    .checked_add_signed(TimeDelta::hours(1))
+                    .ok_or(QueryError::OutOfRange)?;
+
+                Ok(self
+                    .tz
+                    .from_local_datetime(&later)
+                    .earliest()
+                    .unwrap_or_else(|| self.tz.from_utc_datetime(&wall)))
+            }
+        }
+    }
+}
+
+/// `type` of `/events`, repeated or comma-separated; every kind when absent.
+pub fn parse_event_filter(query: Option<&str>) -> Result<Filter, QueryError> {
+    let mut filter = None;
+
+    for pair in query.unwrap_or_default().split('&') {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        if decode(key)? != "type" {
+            continue;
+        }
+
+        for kind in decode(value)?.split(',') {
+            let kind = Kind::parse(kind).ok_or_else(|| QueryError::Invalid {
+                param: "type",
+                value: kind.into(),
+            })?;
+            filter = Some(filter.unwrap_or_else(Filter::none).with(kind));
+        }
+    }
+
+    Ok(filter.unwrap_or(Filter::ALL))
+}
+
+fn set<T>(slot: &mut Option<T>, param: &'static str, value: T) -> Result<(), QueryError> {
+    if slot.is_some() {
+        return Err(QueryError::Duplicate(param));
+    }
+
+    *slot = Some(value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rinha_http::PaymentsSummaryError;
+    use hyper::StatusCode;
+
+    /// 2025-07-01T12:00:00Z
+    const NOON: i64 = 1_751_371_200_000_000;
+    /// 2025-07-01T00:00:00Z
+    const MIDNIGHT: i64 = 1_751_328_000_000_000;
+    const DAY: i64 = 86_400_000_000;
+
+    fn parse(query: &str) -> Result<SummaryQuery, QueryError> {
+        SummaryQuery::parse(Some(query))
+    }
+
+    fn assert_bad_request(err: QueryError) {
+        assert_eq!(
+            PaymentsSummaryError::from(err).status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn offset_may_be_percent_encoded_or_literal() {
+        let encoded = parse("from=2025-07-01T12%3A00%3A00%2B00%3A00").unwrap();
+        let literal = parse("from=2025-07-01T12:00:00+00:00").unwrap();
+
+        assert_eq!(encoded.from, NOON);
+        assert_eq!(literal.from, NOON);
+    }
+
+    #[test]
+    fn instant_may_be_epoch_millis() {
+        let query = parse("from=1751371200000&to=1751371200001").unwrap();
+
+        assert_eq!(query.from, NOON);
+        assert_eq!(query.to, NOON + 1_000);
+    }
+
+    #[test]
+    fn date_covers_its_whole_day() {
+        let query = parse("from=2025-07-01&to=2025-07-01").unwrap();
+
+        assert_eq!(query.from, MIDNIGHT);
+        assert_eq!(query.to, MIDNIGHT + DAY - 1);
+    }
+
+    #[test]
+    fn unparseable_instant_is_bad_request() {
+        for query in [
+            "from=yesterday",
+            "to=2025-13-01",
+            "from=2025-07-01T12:00:00",
+        ] {
+            let err = parse(query).unwrap_err();
+            assert!(matches!(err, QueryError::Invalid { .. }), "{query}");
+            assert_bad_request(err);
+        }
+    }
+
+    #[test]
+    fn inverted_range_is_bad_request() {
+        let err = parse("from=2025-07-01T12:00:00.000001Z&to=2025-07-01T12:00:00Z").unwrap_err();
+
+        assert!(matches!(err, QueryError::Inverted));
+        assert_bad_request(err);
+    }
+
+    #[test]
+    fn bounds_are_inclusive_to_the_microsecond() {
+        let query = parse("from=2025-07-01T12:00:00Z&to=2025-07-01T12:00:00Z").unwrap();
+        assert_eq!((query.from, query.to), (NOON, NOON));
+
+        let query =
+            parse("from=2025-07-01T12:00:00.000001Z&to=2025-07-01T12:00:00.999999Z&interval=1s")
+                .unwrap();
+        let buckets = query.buckets().unwrap().unwrap();
+
+        assert_eq!(buckets.bounds, vec![NOON + 1, NOON + 1_000_000]);
+    }
+
+    #[test]
+    fn huge_interval_is_bad_request_not_a_panic() {
+        let err = parse("from=2025-07-01&to=2025-07-02&interval=100000000d")
+            .unwrap()
+            .buckets()
+            .unwrap_err();
+
+        assert!(matches!(err, QueryError::OutOfRange));
+        assert_bad_request(err);
+    }
+
+    #[test]
+    fn interval_longer_than_the_window_is_one_bucket() {
+        let query =
+            parse("from=2025-07-01T12:00:00Z&to=2025-07-01T12:00:00Z&interval=3650d").unwrap();
+        let buckets = query.buckets().unwrap().unwrap();
+
+        assert_eq!(buckets.bounds, vec![NOON, NOON + 1]);
+    }
+
+    #[test]
+    fn too_many_buckets_are_refused_upfront() {
+        let err = parse("from=2000-01-01&to=2025-07-01&interval=1s")
+            .unwrap()
+            .buckets()
+            .unwrap_err();
+
+        assert!(matches!(err, QueryError::TooManyBuckets(MAX_BUCKETS)));
+    }
+
+    #[test]
+    fn interval_alone_covers_a_bounded_window() {
+        let query = parse("to=2025-07-01T12:00:00Z&interval=1d").unwrap();
+        assert_eq!(query.from, NOON - DEFAULT_BUCKETS as i64 * DAY);
+
+        let buckets = query.buckets().unwrap().unwrap();
+        assert_eq!(buckets.bounds.len(), DEFAULT_BUCKETS as usize + 2);
+    }
+}
@@ -0,0 +1,290 @@
+use crate::{
+    rinha_ambulance::{self, UpstreamType},
+    rinha_chan,
+};
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicI64, AtomicU64, Ordering},
+    time::Duration,
+};
+
+pub const METRICS_PATH: &str = "/metrics";
+pub const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Upper bounds, in seconds, of the upstream latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// Why a payment was refused after it parsed, each its own series.
+#[derive(Debug, Clone, Copy)]
+pub enum Rejected {
+    Duplicate,
+    StorageFull,
+    Expired,
+    QueueFull,
+    Closed,
+}
+
+const REJECTIONS: [(Rejected, &str); 5] = [
+    (Rejected::Duplicate, "duplicate"),
+    (Rejected::StorageFull, "storage_full"),
+    (Rejected::Expired, "expired"),
+    (Rejected::QueueFull, "queue_full"),
+    (Rejected::Closed, "closed"),
+];
+
+const PROCESSORS: [(UpstreamType, &str); 2] = [
+    (UpstreamType::Default, "default"),
+    (UpstreamType::Fallback, "fallback"),
+];
+
+struct Histogram {
+    /// per bucket, not cumulative; the last one catches everything slower
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; LATENCY_BUCKETS.len() + 1],
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|le| secs <= *le)
+            .unwrap_or(LATENCY_BUCKETS.len());
+
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+// Every series is a relaxed atomic bumped in place, so recording never takes
+// a lock on the payment path; `render` reads them all at scrape time.
+static ACCEPTED: AtomicU64 = AtomicU64::new(0);
+static INVALID: AtomicU64 = AtomicU64::new(0);
+static REJECTED: [AtomicU64; REJECTIONS.len()] = [const { AtomicU64::new(0) }; REJECTIONS.len()];
+static RETRIED: AtomicU64 = AtomicU64::new(0);
+static PROCESSED: [AtomicU64; 2] = [const { AtomicU64::new(0) }; 2];
+static FAILED: [AtomicU64; 2] = [const { AtomicU64::new(0) }; 2];
+static LATENCY: [Histogram; 2] = [const { Histogram::new() }; 2];
+static MIN_RESPONSE_TIME: [AtomicI64; 2] = [const { AtomicI64::new(0) }; 2];
+
+fn idx(processor: &UpstreamType) -> usize {
+    match processor {
+        UpstreamType::Default => 0,
+        UpstreamType::Fallback => 1,
+    }
+}
+
+pub fn accepted() {
+    ACCEPTED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Refused by the admission checks or the channels, once it parsed.
+pub fn rejected(reason: Rejected) {
+    REJECTED[reason as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Refused because it is not a payment.
+pub fn invalid() {
+    INVALID.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn retried() {
+    RETRIED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn processed(processor: &UpstreamType) {
+    PROCESSED[idx(processor)].fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn failed(processor: &UpstreamType) {
+    FAILED[idx(processor)].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Round trip of one payment request to `processor`, whatever its outcome.
+pub fn upstream_latency(processor: &UpstreamType, elapsed: Duration) {
+    LATENCY[idx(processor)].observe(elapsed);
+}
+
+/// `minResponseTime` last reported by the health check of `processor`.
+pub fn upstream_min_response_time(processor: &UpstreamType, millis: i32) {
+    MIN_RESPONSE_TIME[idx(processor)].store(millis as i64, Ordering::Relaxed);
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Every series in the Prometheus text exposition format.
+pub fn render() -> String {
+    let mut out = String::with_capacity(4096);
+
+    header(
+        &mut out,
+        "rinha_payments_accepted_total",
+        "counter",
+        "Payments admitted and enqueued.",
+    );
+    let _ = writeln!(
+        out,
+        "rinha_payments_accepted_total {}",
+        ACCEPTED.load(Ordering::Relaxed)
+    );
+
+    header(
+        &mut out,
+        "rinha_payments_rejected_total",
+        "counter",
+        "Payments refused at ingestion, by reason.",
+    );
+    let _ = writeln!(
+        out,
+        "rinha_payments_rejected_total{{reason=\"invalid\"}} {}",
+        INVALID.load(Ordering::Relaxed)
+    );
+    for (reason, label) in REJECTIONS {
+        let _ = writeln!(
+            out,
+            "rinha_payments_rejected_total{{reason=\"{label}\"}} {}",
+            REJECTED[reason as usize].load(Ordering::Relaxed)
+        );
+    }
+
+    header(
+        &mut out,
+        "rinha_payments_retried_total",
+        "counter",
+        "Attempts that failed and were retried.",
+    );
+    let _ = writeln!(
+        out,
+        "rinha_payments_retried_total {}",
+        RETRIED.load(Ordering::Relaxed)
+    );
+
+    header(
+        &mut out,
+        "rinha_payments_processed_total",
+        "counter",
+        "Payments accepted by a processor.",
+    );
+    for (processor, label) in &PROCESSORS {
+        let _ = writeln!(
+            out,
+            "rinha_payments_processed_total{{processor=\"{label}\"}} {}",
+            PROCESSED[idx(processor)].load(Ordering::Relaxed)
+        );
+    }
+
+    header(
+        &mut out,
+        "rinha_payments_failed_total",
+        "counter",
+        "Payments refused by a processor and given up.",
+    );
+    for (processor, label) in &PROCESSORS {
+        let _ = writeln!(
+            out,
+            "rinha_payments_failed_total{{processor=\"{label}\"}} {}",
+            FAILED[idx(processor)].load(Ordering::Relaxed)
+        );
+    }
+
+    header(
+        &mut out,
+        "rinha_queue_depth",
+        "gauge",
+        "Payments waiting in each ingestion channel.",
+    );
+    for (channel, depth) in rinha_chan::depths().iter().enumerate() {
+        let _ = writeln!(out, "rinha_queue_depth{{channel=\"{channel}\"}} {depth}");
+    }
+
+    header(
+        &mut out,
+        "rinha_queue_drain_rate",
+        "gauge",
+        "Payments taken off the channels over the last second.",
+    );
+    let _ = writeln!(
+        out,
+        "rinha_queue_drain_rate {}",
+        rinha_chan::stats().drain_rate
+    );
+
+    header(
+        &mut out,
+        "rinha_upstream_request_duration_seconds",
+        "histogram",
+        "Round trip of payment requests to each processor.",
+    );
+    for (processor, label) in &PROCESSORS {
+        let histogram = &LATENCY[idx(processor)];
+        let mut cumulative = 0;
+
+        for (bucket, le) in histogram.buckets.iter().zip(
+            LATENCY_BUCKETS
+                .iter()
+                .map(|le| le.to_string())
+                .chain(["+Inf".to_string()]),
+        ) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "rinha_upstream_request_duration_seconds_bucket{{processor=\"{label}\",le=\"{le}\"}} {cumulative}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "rinha_upstream_request_duration_seconds_sum{{processor=\"{label}\"}} {}",
+            histogram.sum_micros.load(Ordering::Relaxed) as f64 / 1e6
+        );
+        let _ = writeln!(
+            out,
+            "rinha_upstream_request_duration_seconds_count{{processor=\"{label}\"}} {cumulative}"
+        );
+    }
+
+    let health = rinha_ambulance::health();
+    header(
+        &mut out,
+        "rinha_upstream_healthy",
+        "gauge",
+        "Health of each processor as last checked, absent before the first check.",
+    );
+    for (label, healthy) in [("default", health.default), ("fallback", health.fallback)] {
+        if let Some(healthy) = healthy {
+            let _ = writeln!(
+                out,
+                "rinha_upstream_healthy{{processor=\"{label}\"}} {}",
+                healthy as u8
+            );
+        }
+    }
+
+    header(
+        &mut out,
+        "rinha_upstream_min_response_time_seconds",
+        "gauge",
+        "minResponseTime last reported by each processor.",
+    );
+    for (processor, label) in &PROCESSORS {
+        let _ = writeln!(
+            out,
+            "rinha_upstream_min_response_time_seconds{{processor=\"{label}\"}} {}",
+            MIN_RESPONSE_TIME[idx(processor)].load(Ordering::Relaxed) as f64 / 1e3
+        );
+    }
+
+    out
+}
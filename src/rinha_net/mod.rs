@@ -1,30 +1,64 @@
-use crate::rinha_http;
-use http_body_util::Full;
+use crate::{
+    rinha_chan, rinha_conf,
+    rinha_events::{self, EventBody},
+    rinha_federation, rinha_http, rinha_metrics, rinha_storage,
+};
+use http_body_util::{Either, Full};
 use hyper::{
-    Method, Request, Response,
-    body::{Bytes, Incoming},
-    server, service,
+    Method, Request, Response, StatusCode,
+    body::{Body, Bytes, Frame, Incoming, SizeHint},
+    service,
 };
 use hyper_util::{
-    client::legacy::{Client, connect::HttpConnector},
+    client::{
+        self,
+        legacy::{Client, connect::HttpConnector},
+    },
     rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto,
 };
 use socket2::{Domain, Protocol, SockAddr, Socket, TcpKeepalive, Type};
-use std::{net::SocketAddr, sync::LazyLock};
+use std::{
+    convert::Infallible,
+    error::Error,
+    fs, io,
+    net::SocketAddr,
+    os::unix::{fs::FileTypeExt, fs::PermissionsExt, net::UnixStream as StdUnixStream},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        Arc, LazyLock,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    task::{Context, Poll},
+};
 use tokio::{
-    net::{TcpListener, ToSocketAddrs, lookup_host},
-    time::Duration,
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream, ToSocketAddrs, UnixListener, UnixStream, lookup_host},
+    signal::unix::{SignalKind, signal},
+    sync::{Notify, watch},
+    task::JoinSet,
+    time::{Duration, Instant, sleep, sleep_until, timeout},
 };
 
 pub const JSON_CONTENT_TYPE: &str = "application/json";
+pub const UNIX_PREFIX: &str = "unix:";
+
+static CLIENT: LazyLock<Client<HttpConnector, Full<Bytes>>> =
+    LazyLock::new(|| client_builder().build(http_connector()));
 
-static CLIENT: LazyLock<Client<HttpConnector, Full<Bytes>>> = LazyLock::new(|| {
+/// Pooling shared by every client this crate builds.
+pub fn client_builder() -> client::legacy::Builder {
     let mut client = Client::builder(TokioExecutor::new());
     client.pool_timer(TokioTimer::new());
     client.pool_idle_timeout(Duration::from_secs(30));
     client.pool_max_idle_per_host(8);
     client.retry_canceled_requests(false);
 
+    client
+}
+
+pub fn http_connector() -> HttpConnector {
     let mut conn = HttpConnector::new();
     conn.set_keepalive(Some(Duration::from_secs(30)));
     conn.set_keepalive_interval(Some(Duration::from_secs(10)));
@@ -34,8 +68,8 @@ static CLIENT: LazyLock<Client<HttpConnector, Full<Bytes>>> = LazyLock::new(|| {
     conn.set_connect_timeout(Some(Duration::from_millis(500)));
     conn.set_happy_eyeballs_timeout(Some(Duration::from_millis(100)));
 
-    client.build(conn)
-});
+    conn
+}
 
 #[derive(thiserror::Error, Debug)]
 pub enum ResolveSocketAddrError {
@@ -77,6 +111,97 @@ pub fn create_tcp_socket(addr: SocketAddr) -> Result<Socket, CreateTCPSocketErro
     Ok(socket)
 }
 
+#[derive(thiserror::Error, Debug)]
+pub enum CreateUnixSocketError {
+    #[error("io")]
+    IO(#[from] std::io::Error),
+    #[error("{0} is in use by a live server")]
+    InUse(String),
+    #[error("{0} exists and is not a socket")]
+    NotSocket(String),
+}
+
+/// Binds `path` with `mode` permissions. A socket file left behind by a
+/// server that is gone is removed first; one still accepting is not.
+pub fn create_unix_socket(path: &Path, mode: u32) -> Result<Socket, CreateUnixSocketError> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(CreateUnixSocketError::NotSocket(path.display().to_string()));
+        }
+        if StdUnixStream::connect(path).is_ok() {
+            return Err(CreateUnixSocketError::InUse(path.display().to_string()));
+        }
+
+        fs::remove_file(path)?;
+    }
+
+    let socket = Socket::new(Domain::UNIX, Type::STREAM, None)?;
+    socket.set_nonblocking(true)?;
+    socket.set_send_buffer_size(*rinha_conf::RINHA_SOCKET_BUFFER_BYTES)?;
+    socket.set_recv_buffer_size(*rinha_conf::RINHA_SOCKET_BUFFER_BYTES)?;
+
+    socket.bind(&SockAddr::unix(path)?)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    socket.listen(8 * 1024)?;
+
+    Ok(socket)
+}
+
+pub enum Listener {
+    TCP(TcpListener),
+    Unix(UnixListener),
+}
+
+/// A listening socket not yet registered with any runtime.
+pub enum Bound {
+    TCP(Socket),
+    Unix(Socket),
+}
+
+impl Bound {
+    /// Registers the socket with the runtime of the calling thread.
+    pub fn listen(self) -> Result<Listener, std::io::Error> {
+        Ok(match self {
+            Self::TCP(socket) => Listener::TCP(TcpListener::from_std(socket.into())?),
+            Self::Unix(socket) => Listener::Unix(UnixListener::from_std(socket.into())?),
+        })
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum BindError {
+    #[error("io")]
+    IO(#[from] std::io::Error),
+    #[error("resolve socket")]
+    ResolveSocket(#[from] ResolveSocketAddrError),
+    #[error("create tcp socket")]
+    CreateTCPSocket(#[from] CreateTCPSocketError),
+    #[error("create unix socket")]
+    CreateUnixSocket(#[from] CreateUnixSocketError),
+}
+
+/// `count` sockets listening on `addr`, `host:port` or `unix:<path>`: over
+/// TCP each is its own `SO_REUSEPORT` socket, so the kernel spreads
+/// connections across them; a Unix socket file can only be bound once, so
+/// they share it and accept from the same queue.
+pub async fn bind(addr: &str, count: usize) -> Result<Vec<Bound>, BindError> {
+    if let Some(path) = addr.strip_prefix(UNIX_PREFIX) {
+        let socket = create_unix_socket(Path::new(path), *rinha_conf::RINHA_UNIX_SOCKET_MODE)?;
+        let mut sockets = (1..count)
+            .map(|_| socket.try_clone().map(Bound::Unix))
+            .collect::<Result<Vec<_>, _>>()?;
+        sockets.push(Bound::Unix(socket));
+
+        return Ok(sockets);
+    }
+
+    let addr = resolve_socket_addr(addr).await?;
+
+    Ok((0..count)
+        .map(|_| create_tcp_socket(addr).map(Bound::TCP))
+        .collect::<Result<_, _>>()?)
+}
+
 fn set_sock_opt_conf(socket: &Socket) -> Result<(), std::io::Error> {
     let mut keepalive = TcpKeepalive::new();
     keepalive = keepalive.with_time(Duration::from_secs(30));
@@ -92,8 +217,8 @@ fn set_sock_opt_conf(socket: &Socket) -> Result<(), std::io::Error> {
 
     socket.set_tos_v4(0x10)?;
 
-    socket.set_send_buffer_size(32 * 1024)?;
-    socket.set_recv_buffer_size(32 * 1024)?;
+    socket.set_send_buffer_size(*rinha_conf::RINHA_SOCKET_BUFFER_BYTES)?;
+    socket.set_recv_buffer_size(*rinha_conf::RINHA_SOCKET_BUFFER_BYTES)?;
 
     socket.set_tcp_user_timeout(Some(Duration::from_secs(3)))?;
     socket.set_linger(Some(Duration::ZERO))?;
@@ -111,53 +236,695 @@ pub enum AcceptLoopError {
     IO(#[from] std::io::Error),
 }
 
-pub async fn accept_loop(tcp_listener: TcpListener) -> Result<(), AcceptLoopError> {
-    let mut http = server::conn::http1::Builder::new();
+/// Accept loops running, one per `RINHA_THREADS`.
+static ACCEPTING: AtomicUsize = AtomicUsize::new(0);
+/// Flips to `true` once, when the server starts shutting down.
+static SHUTDOWN: LazyLock<watch::Sender<bool>> = LazyLock::new(|| watch::Sender::new(false));
+
+/// Whether an accept loop is up and has not bailed out.
+pub fn is_accepting() -> bool {
+    ACCEPTING.load(Ordering::Relaxed) > 0
+}
+
+/// Stops every accept loop and lets open connections finish their current
+/// request before closing.
+pub fn shutdown() {
+    SHUTDOWN.send_replace(true);
+}
+
+/// `body` holding on to `guard` until it ends, fails or is dropped, so that
+/// whatever the guard counts lasts as long as the response is streamed.
+pub struct Guarded<B, G> {
+    body: B,
+    guard: Option<G>,
+}
+
+impl<B, G> Guarded<B, G> {
+    pub fn new(body: B, guard: G) -> Self {
+        Self {
+            body,
+            guard: Some(guard),
+        }
+    }
+}
+
+impl<B: Body + Unpin, G: Unpin> Body for Guarded<B, G> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let frame = Pin::new(&mut self.body).poll_frame(cx);
+        if matches!(frame, Poll::Ready(None | Some(Err(_)))) {
+            self.guard = None;
+        }
+
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+/// Answers every request an accept loop reads: `router` for a rinha
+/// instance, or any `async fn` of the same shape.
+pub trait Handler: Clone + Send + Sync + 'static {
+    type Body: Body<Data: Send, Error: Into<Box<dyn Error + Send + Sync>>> + Send + Unpin + 'static;
+
+    fn handle(
+        &self,
+        req: Request<Incoming>,
+    ) -> impl Future<Output = Result<Response<Self::Body>, Infallible>> + Send + use<Self>;
+}
+
+impl<H, F, B> Handler for H
+where
+    H: Fn(Request<Incoming>) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = Result<Response<B>, Infallible>> + Send + 'static,
+    B: Body<Data: Send, Error: Into<Box<dyn Error + Send + Sync>>> + Send + Unpin + 'static,
+{
+    type Body = B;
+
+    fn handle(
+        &self,
+        req: Request<Incoming>,
+    ) -> impl Future<Output = Result<Response<B>, Infallible>> + Send + use<H, F, B> {
+        self(req)
+    }
+}
+
+pub async fn accept_loop(listener: Listener, handler: impl Handler) -> Result<(), AcceptLoopError> {
+    let mut connections = JoinSet::new();
+    let path = listener.path();
+
+    ACCEPTING.fetch_add(1, Ordering::Relaxed);
+    let res = serve(listener, handler, &mut connections).await;
+    // the loops bound to a socket file share it, so only the last one out
+    // takes it down
+    if ACCEPTING.fetch_sub(1, Ordering::Relaxed) == 1
+        && let Some(path) = path
+        && let Err(err) = fs::remove_file(&path)
+    {
+        tracing::warn!(?err, ?path, "removing unix socket");
+    }
+
+    drain(connections).await;
+
+    res
+}
+
+/// Waits up to `RINHA_SHUTDOWN_GRACE_MS` for open connections to close,
+/// then aborts the rest.
+async fn drain(mut connections: JoinSet<()>) {
+    let grace = Duration::from_millis(*rinha_conf::RINHA_SHUTDOWN_GRACE_MS);
+    let drained = timeout(grace, async {
+        while connections.join_next().await.is_some() {}
+    })
+    .await;
+
+    if drained.is_err() {
+        tracing::warn!(open = connections.len(), "aborting connections");
+    }
+}
+
+enum Accepted {
+    TCP(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Listener {
+    /// The socket file a Unix listener is bound to.
+    fn path(&self) -> Option<PathBuf> {
+        match self {
+            Self::TCP(_) => None,
+            Self::Unix(unix_listener) => unix_listener
+                .local_addr()
+                .ok()?
+                .as_pathname()
+                .map(Path::to_path_buf),
+        }
+    }
 
-    http.writev(false);
-    http.timer(TokioTimer::new());
-    http.pipeline_flush(false);
-    http.half_close(true);
-    http.keep_alive(false);
-    http.header_read_timeout(Duration::from_millis(100));
-    http.max_buf_size(16 * 1024);
+    async fn accept(&self) -> Result<Accepted, std::io::Error> {
+        Ok(match self {
+            Self::TCP(tcp_listener) => {
+                let (stream, _) = tcp_listener.accept().await?;
+                let socket = socket2::SockRef::from(&stream);
+                let _ = socket.set_tcp_nodelay(true);
+                let _ = socket.set_tcp_quickack(true);
+
+                Accepted::TCP(stream)
+            }
+            Self::Unix(unix_listener) => Accepted::Unix(unix_listener.accept().await?.0),
+        })
+    }
+}
 
-    let service = service::service_fn(router);
+async fn serve(
+    listener: Listener,
+    handler: impl Handler,
+    connections: &mut JoinSet<()>,
+) -> Result<(), AcceptLoopError> {
+    let keep_alive = *rinha_conf::RINHA_HTTP_KEEP_ALIVE;
+
+    // HTTP/1.1, or HTTP/2 when the client opens with its preface (h2c with
+    // prior knowledge)
+    let mut http = auto::Builder::new(TokioExecutor::new());
+
+    http.http1()
+        .writev(false)
+        .timer(TokioTimer::new())
+        .pipeline_flush(false)
+        .half_close(true)
+        .keep_alive(keep_alive)
+        // hyper would start it as soon as a kept-alive connection waits for
+        // its next request; `spawn_connection` times the head instead
+        .header_read_timeout(None)
+        .max_buf_size(*rinha_conf::RINHA_HTTP_MAX_BUF_SIZE);
+    http.http2()
+        .timer(TokioTimer::new())
+        .max_concurrent_streams(*rinha_conf::RINHA_HTTP2_MAX_CONCURRENT_STREAMS);
+
+    let mut shutdown = SHUTDOWN.subscribe();
 
     loop {
-        let http = http.clone();
-        let (stream, _) = tcp_listener.accept().await?;
-        let socket = socket2::SockRef::from(&stream);
-        let _ = socket.set_tcp_nodelay(true);
-        let _ = socket.set_tcp_quickack(true);
-
-        tokio::spawn(async move {
-            let io = TokioIo::new(stream);
-            if let Err(err) = http.serve_connection(io, service).await {
-                tracing::error!(?err, "accept loop");
+        let accepted = tokio::select! {
+            _ = shutdown.wait_for(|down| *down) => return Ok(()),
+            Some(_) = connections.join_next() => continue,
+            accepted = listener.accept() => accepted?,
+        };
+
+        match accepted {
+            Accepted::TCP(stream) => spawn_connection(&http, &handler, stream, connections),
+            Accepted::Unix(stream) => spawn_connection(&http, &handler, stream, connections),
+        }
+    }
+}
+
+/// What a connection task and its service share.
+#[derive(Default)]
+struct ConnectionState {
+    served: AtomicU64,
+    /// requests whose response is not fully sent yet
+    in_flight: AtomicUsize,
+    /// woken when a request starts and when the last one in flight ends,
+    /// arming or disarming the idle timer
+    activity: Notify,
+    /// woken once the connection served `RINHA_HTTP_MAX_REQUESTS`
+    exhausted: Notify,
+    /// bytes of a request arrived and its head is not complete yet
+    reading_head: AtomicBool,
+    /// HTTP/2, whose frames between streams are no request head
+    multiplexed: AtomicBool,
+}
+
+/// `stream` telling its connection when the next request starts to arrive,
+/// which arms the header timeout.
+struct Watched<S> {
+    stream: S,
+    state: Arc<ConnectionState>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Watched<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let start = buf.filled().len();
+        let res = Pin::new(&mut self.stream).poll_read(cx, buf);
+
+        let read = &buf.filled()[start..];
+        if !read.is_empty() {
+            let state = &self.state;
+            if start == 0 && read.starts_with(b"PRI ") {
+                state.multiplexed.store(true, Ordering::Relaxed);
+            }
+            if !state.multiplexed.load(Ordering::Relaxed)
+                && state.in_flight.load(Ordering::Relaxed) == 0
+                && !state.reading_head.swap(true, Ordering::Relaxed)
+            {
+                state.activity.notify_one();
+            }
+        }
+
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Watched<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// Counts a request as in flight on its connection until dropped.
+struct InFlight(Arc<ConnectionState>);
+
+impl InFlight {
+    fn new(state: Arc<ConnectionState>) -> Self {
+        if state.in_flight.fetch_add(1, Ordering::Relaxed) == 0 {
+            state.activity.notify_one();
+        }
+        Self(state)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.0.activity.notify_one();
+        }
+    }
+}
+
+fn spawn_connection<S>(
+    http: &auto::Builder<TokioExecutor>,
+    handler: &impl Handler,
+    stream: S,
+    connections: &mut JoinSet<()>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let http = http.clone();
+    let state = Arc::new(ConnectionState::default());
+    let service = {
+        let state = state.clone();
+        let handler = handler.clone();
+        service::service_fn(move |req| {
+            let state = state.clone();
+            let res = handler.handle(req);
+            async move {
+                state.reading_head.store(false, Ordering::Relaxed);
+                let in_flight = InFlight::new(state.clone());
+                let served = state.served.fetch_add(1, Ordering::Relaxed) + 1;
+                let max_requests = *rinha_conf::RINHA_HTTP_MAX_REQUESTS;
+                if max_requests > 0 && served == max_requests {
+                    state.exhausted.notify_one();
+                }
+
+                let res = res.await?;
+                Ok::<_, Infallible>(res.map(|body| Guarded::new(body, in_flight)))
+            }
+        })
+    };
+    let mut shutdown = SHUTDOWN.subscribe();
+
+    connections.spawn(async move {
+        let idle_timeout = Duration::from_millis(*rinha_conf::RINHA_HTTP_IDLE_TIMEOUT_MS);
+        let header_timeout = Duration::from_millis(*rinha_conf::RINHA_HTTP_HEADER_TIMEOUT_MS);
+        let keep_alive = *rinha_conf::RINHA_HTTP_KEEP_ALIVE;
+        // without keep-alive there is no wait between requests: the head is
+        // due from the start
+        state.reading_head.store(!keep_alive, Ordering::Relaxed);
+        let stream = Watched {
+            stream,
+            state: state.clone(),
+        };
+        let conn = http.serve_connection(TokioIo::new(stream), service);
+        tokio::pin!(conn);
+
+        // closing gracefully lets the requests in progress finish: HTTP/1.1
+        // answers with `connection: close`, HTTP/2 sends a GOAWAY
+        let mut closing = false;
+        let mut head_deadline = None;
+        let res = loop {
+            head_deadline = match state.reading_head.load(Ordering::Relaxed) {
+                true => head_deadline.or_else(|| Some(Instant::now() + header_timeout)),
+                false => None,
             };
-        });
+
+            tokio::select! {
+                res = conn.as_mut() => break res,
+                _ = shutdown.wait_for(|down| *down), if !closing => {
+                    closing = true;
+                    conn.as_mut().graceful_shutdown();
+                }
+                _ = state.exhausted.notified(), if !closing => {
+                    closing = true;
+                    conn.as_mut().graceful_shutdown();
+                }
+                _ = state.activity.notified() => {}
+                // only between requests: a long one, or an event stream, is
+                // never cut short for being idle
+                _ = sleep(idle_timeout), if keep_alive && !closing
+                    && state.in_flight.load(Ordering::Relaxed) == 0
+                    && head_deadline.is_none() => {
+                    closing = true;
+                    conn.as_mut().graceful_shutdown();
+                }
+                // a head trickling in is cut off, however long the
+                // connection may idle between requests
+                _ = sleep_until(head_deadline.unwrap_or_else(Instant::now)),
+                    if head_deadline.is_some() => {
+                    tracing::debug!("request head timed out");
+                    break Ok(());
+                }
+            }
+        };
+
+        if let Err(err) = res {
+            tracing::error!(?err, "accept loop");
+        }
+    });
+}
+
+/// Waits for SIGTERM or SIGINT, then shuts the server down.
+pub async fn task() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(err) => {
+            tracing::error!(?err, "net task");
+            return;
+        }
+    };
+
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
     }
+
+    tracing::info!("shutting down server...");
+    shutdown();
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum RouterError {
     #[error("payments")]
     Payments(#[from] rinha_http::PaymentsError),
+    #[error("payments batch")]
+    PaymentsBatch(#[from] rinha_http::PaymentsBatchError),
+    #[error("payment status")]
+    PaymentStatus(#[from] rinha_http::PaymentStatusError),
     #[error("payments summary")]
     PaymentsSummary(#[from] rinha_http::PaymentsSummaryError),
+    #[error("purge payments")]
+    PurgePayments(#[from] rinha_http::PurgePaymentsError),
+    #[error("storage footprint")]
+    StorageFootprint(#[from] rinha_http::StorageFootprintError),
+    #[error("queue stats")]
+    QueueStats(#[from] rinha_http::QueueStatsError),
+    #[error("metrics")]
+    Metrics(#[from] rinha_http::MetricsError),
+    #[error("events")]
+    Events(#[from] rinha_http::EventsError),
+    #[error("probe")]
+    Probe(#[from] rinha_http::ProbeError),
     #[error("not found")]
     NotFound(#[from] rinha_http::NotFoundError),
 }
 
-pub async fn router(req: Request<Incoming>) -> Result<Response<Full<Bytes>>, RouterError> {
-    match (req.method(), req.uri().path()) {
-        (&Method::POST, "/payments") => Ok(rinha_http::payments(req).await?),
-        (&Method::GET, "/payments-summary") => Ok(rinha_http::payments_summary(req).await?),
-        _ => Ok(rinha_http::not_found().await?),
+impl RouterError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Payments(err) => err.status(),
+            Self::PaymentsSummary(err) => err.status(),
+            Self::PaymentsBatch(err) => err.status(),
+            Self::Events(err) => err.status(),
+            Self::PaymentStatus(_)
+            | Self::PurgePayments(_)
+            | Self::StorageFootprint(_)
+            | Self::QueueStats(_)
+            | Self::Metrics(_)
+            | Self::Probe(_)
+            | Self::NotFound(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
     }
+
+    /// What went wrong for client errors, the innermost cause being the most
+    /// telling; server errors only carry their reason phrase, details go to
+    /// the log.
+    pub fn message(&self) -> String {
+        let status = self.status();
+
+        if status.is_server_error() {
+            return status.canonical_reason().unwrap_or("error").to_lowercase();
+        }
+
+        let mut cause: &dyn Error = self;
+        while let Some(source) = cause.source() {
+            cause = source;
+        }
+
+        cause.to_string()
+    }
+
+    /// `{"error": message}` with `status`, logging server errors.
+    pub fn response(&self) -> Response<Full<Bytes>> {
+        let status = self.status();
+        if status.is_server_error() {
+            tracing::error!(err = ?self, "router");
+        }
+
+        rinha_http::error_response(status, &self.message())
+    }
+}
+
+/// Responses are buffered, except for the event stream.
+pub type ResponseBody = Either<Full<Bytes>, EventBody>;
+
+/// Every error becomes a response, so a failing handler never costs the
+/// client its connection.
+pub async fn router(req: Request<Incoming>) -> Result<Response<ResponseBody>, Infallible> {
+    let res = route(req)
+        .await
+        .unwrap_or_else(|err| err.response().map(Either::Left));
+
+    Ok(res)
+}
+
+async fn route(req: Request<Incoming>) -> Result<Response<ResponseBody>, RouterError> {
+    let res = match (req.method(), req.uri().path()) {
+        (&Method::POST, "/payments") => rinha_http::payments(req).await?,
+        (&Method::POST, rinha_http::PAYMENTS_BATCH_PATH) => rinha_http::payments_batch(req).await?,
+        (&Method::GET, "/payments-summary") => rinha_http::payments_summary(req).await?,
+        (&Method::GET, "/healthz") => rinha_http::healthz().await?,
+        (&Method::GET, "/readyz") => rinha_http::readyz().await?,
+        (&Method::POST, "/purge-payments") => rinha_http::purge_payments().await?,
+        (&Method::GET, rinha_events::EVENTS_PATH) => {
+            return Ok(rinha_http::events(req).await?.map(Either::Right));
+        }
+        (&Method::GET, path) if path.starts_with(rinha_http::PAYMENT_STATUS_PREFIX) => {
+            rinha_http::payment_status(req).await?
+        }
+        (&Method::GET, rinha_federation::INTERNAL_SUMMARY_PATH) => {
+            rinha_http::internal_payments_summary(req).await?
+        }
+        (&Method::GET, rinha_storage::INTERNAL_STORAGE_PATH) => {
+            rinha_http::storage_footprint().await?
+        }
+        (&Method::GET, rinha_chan::INTERNAL_QUEUES_PATH) => rinha_http::queue_stats().await?,
+        (&Method::GET, rinha_metrics::METRICS_PATH) => rinha_http::metrics().await?,
+        _ => rinha_http::not_found().await?,
+    };
+
+    Ok(res.map(Either::Left))
 }
 
 pub fn bootstrap() {
     LazyLock::force(&CLIENT);
+    LazyLock::force(&SHUTDOWN);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        rinha_chan::Queued,
+        rinha_domain::Payment,
+        rinha_http::{PaymentsError, PaymentsSummaryError, QueryError},
+    };
+    use http_body_util::BodyExt;
+    use std::io::Write;
+    use tokio::sync::mpsc::error::{SendError, TrySendError};
+
+    const PAYMENT: &str =
+        r#"{"correlationId":"4a7901b8-7d26-4d9d-aa19-4dc1c7cf60b3","amount":19.90}"#;
+
+    fn serde_error(json: &str) -> serde_json::Error {
+        serde_json::from_str::<Payment>(json).unwrap_err()
+    }
+
+    fn http_error() -> http::Error {
+        Response::builder().status(1000).body(()).unwrap_err()
+    }
+
+    /// What a client sending garbage instead of a request gets from hyper.
+    async fn hyper_error() -> hyper::Error {
+        let (mut client, server) = StdUnixStream::pair().unwrap();
+        client.write_all(b"garbage\r\n\r\n").unwrap();
+        server.set_nonblocking(true).unwrap();
+
+        hyper::server::conn::http1::Builder::new()
+            .serve_connection(
+                TokioIo::new(UnixStream::from_std(server).unwrap()),
+                service::service_fn(|_: Request<Incoming>| async {
+                    Ok::<_, Infallible>(Response::new(Full::<Bytes>::default()))
+                }),
+            )
+            .await
+            .unwrap_err()
+    }
+
+    fn queued() -> Queued {
+        Queued::new(serde_json::from_str(PAYMENT).unwrap())
+    }
+
+    async fn assert_response(err: impl Into<RouterError>, status: StatusCode, message: &str) {
+        let res = err.into().response();
+        assert_eq!(res.status(), status);
+        assert_eq!(
+            res.headers()[hyper::header::CONTENT_TYPE],
+            JSON_CONTENT_TYPE
+        );
+
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, serde_json::json!({ "error": message }));
+    }
+
+    #[tokio::test]
+    async fn payments_hyper_is_bad_request() {
+        let message = hyper_error().await.to_string();
+        let err = PaymentsError::from(hyper_error().await);
+
+        assert_response(err, StatusCode::BAD_REQUEST, &message).await;
+    }
+
+    #[tokio::test]
+    async fn payments_serde_data_is_unprocessable() {
+        let json = r#"{"correlationId":"4a7901b8-7d26-4d9d-aa19-4dc1c7cf60b3","amount":0}"#;
+        let message = serde_error(json).to_string();
+
+        assert_response(
+            PaymentsError::from(serde_error(json)),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            &message,
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn payments_serde_syntax_is_bad_request() {
+        let json = r#"{"correlationId":"#;
+        let message = serde_error(json).to_string();
+
+        assert_response(
+            PaymentsError::from(serde_error(json)),
+            StatusCode::BAD_REQUEST,
+            &message,
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn payments_http_is_internal() {
+        assert_response(
+            PaymentsError::from(http_error()),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal server error",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn payments_send_is_internal() {
+        assert_response(
+            PaymentsError::from(SendError(queued())),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal server error",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn payments_try_send_full_is_unavailable() {
+        assert_response(
+            PaymentsError::from(TrySendError::Full(queued())),
+            StatusCode::SERVICE_UNAVAILABLE,
+            "service unavailable",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn payments_try_send_closed_is_internal() {
+        assert_response(
+            PaymentsError::from(TrySendError::Closed(queued())),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal server error",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn payments_summary_query_is_bad_request() {
+        assert_response(
+            PaymentsSummaryError::from(QueryError::Inverted),
+            StatusCode::BAD_REQUEST,
+            "`from` is after `to`",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn payments_summary_serde_is_internal() {
+        assert_response(
+            PaymentsSummaryError::from(serde_error("{")),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal server error",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn payments_summary_http_is_internal() {
+        assert_response(
+            PaymentsSummaryError::from(http_error()),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal server error",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn payments_summary_hyper_is_internal() {
+        assert_response(
+            PaymentsSummaryError::from(hyper_error().await),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal server error",
+        )
+        .await;
+    }
 }
@@ -0,0 +1,127 @@
+use crate::{rinha_ambulance::UpstreamType, rinha_conf};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::sync::LazyLock;
+use tokio::time::{Duration, interval};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum State {
+    /// accepted, waiting in a channel
+    #[serde(rename = "queued")]
+    Queued,
+    /// being sent to a processor
+    #[serde(rename = "inFlight")]
+    InFlight,
+    /// the last attempt failed, another one is coming
+    #[serde(rename = "retrying")]
+    Retrying,
+    /// accepted by `processor` and recorded in the ledger
+    #[serde(rename = "processed")]
+    Processed,
+    /// refused by `processor`, never retried
+    #[serde(rename = "givenUp")]
+    GivenUp,
+    /// accepted by `processor` but the ledger failed to record it
+    #[serde(rename = "failed")]
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    pub state: State,
+    pub processor: Option<UpstreamType>,
+    pub attempts: u32,
+    #[serde(rename = "queuedAt")]
+    pub queued_at: DateTime<Utc>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: DateTime<Utc>,
+}
+
+static STATUSES: LazyLock<DashMap<Uuid, Status>> = LazyLock::new(DashMap::new);
+
+fn update(id: Uuid, apply: impl FnOnce(&mut Status)) {
+    if let Some(mut status) = STATUSES.get_mut(&id) {
+        apply(&mut status);
+        status.updated_at = Utc::now();
+    }
+}
+
+pub fn queued(id: Uuid) {
+    let now = Utc::now();
+
+    STATUSES.insert(
+        id,
+        Status {
+            state: State::Queued,
+            processor: None,
+            attempts: 0,
+            queued_at: now,
+            updated_at: now,
+        },
+    );
+}
+
+/// Starts one more attempt against `processor`.
+pub fn in_flight(id: Uuid, processor: &UpstreamType) {
+    update(id, |status| {
+        status.state = State::InFlight;
+        status.processor = Some(processor.clone());
+        status.attempts += 1;
+    });
+}
+
+pub fn retrying(id: Uuid) {
+    update(id, |status| status.state = State::Retrying);
+}
+
+pub fn processed(id: Uuid) {
+    update(id, |status| status.state = State::Processed);
+}
+
+pub fn given_up(id: Uuid) {
+    update(id, |status| status.state = State::GivenUp);
+}
+
+pub fn failed(id: Uuid) {
+    update(id, |status| status.state = State::Failed);
+}
+
+/// Drops the record of a payment that was never enqueued after all.
+pub fn forget(id: Uuid) {
+    STATUSES.remove(&id);
+}
+
+pub fn get(id: &Uuid) -> Option<Status> {
+    STATUSES.get(id).map(|status| status.clone())
+}
+
+pub fn reset() {
+    STATUSES.clear();
+}
+
+/// Forgets settled payments once they are `RINHA_STATUS_TTL_SECS` old;
+/// pending ones stay for as long as they are pending.
+pub async fn task() {
+    let ttl = chrono::Duration::seconds(*rinha_conf::RINHA_STATUS_TTL_SECS as i64);
+    let mut ticker = interval(Duration::from_secs(
+        (*rinha_conf::RINHA_STATUS_TTL_SECS / 4).max(1),
+    ));
+
+    loop {
+        ticker.tick().await;
+
+        let expired_at = Utc::now() - ttl;
+        STATUSES.retain(|_, status| {
+            !matches!(
+                status.state,
+                State::Processed | State::GivenUp | State::Failed
+            ) || status.updated_at > expired_at
+        });
+    }
+}
+
+pub fn bootstrap() {
+    LazyLock::force(&STATUSES);
+}
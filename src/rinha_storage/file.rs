@@ -0,0 +1,210 @@
+use crate::{
+    rinha_ambulance::UpstreamType,
+    rinha_conf,
+    rinha_domain::{Payment, TargetCounter},
+    rinha_storage::{
+        Entry, Footprint, Storage, StorageError,
+        memory::MemoryStorage,
+        snapshot,
+        wal::{self, FsyncPolicy, Wal, WalError},
+    },
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, PoisonError,
+        mpsc::{self, Receiver, Sender},
+    },
+};
+
+/// Most entries the writer thread appends, and flushes, in one go.
+const GROUP_MAX: usize = 4096;
+
+/// In-memory ledgers made durable by a write-ahead log under `dir`, with
+/// periodic snapshots bounding how much of the log a restart replays.
+///
+/// Appends go through a writer thread of their own, so neither the write
+/// nor its `fsync` ever runs on a runtime thread.
+#[derive(Debug)]
+pub struct FileStorage {
+    dir: PathBuf,
+    memory: MemoryStorage,
+    wal: Arc<Mutex<Wal>>,
+    appends: Sender<Entry>,
+    /// held by `checkpoint` and `roll_up`, so no entry moves into a rollup
+    /// between being written as an entry and the rollups being written
+    maintenance: Mutex<()>,
+}
+
+impl FileStorage {
+    /// Loads the newest valid snapshot and replays the log segments written
+    /// after it, then starts a fresh segment for new appends.
+    pub fn open(dir: &Path) -> Result<Self, StorageError> {
+        std::fs::create_dir_all(dir)?;
+
+        let (covers, mut entries, rollups) = match snapshot::load_latest(dir)? {
+            Some(snapshot) => (snapshot.covers, snapshot.entries, snapshot.rollups),
+            None => (0, Vec::new(), Vec::new()),
+        };
+        let from_snapshot = entries.len();
+        entries.extend(wal::replay(dir, covers)?);
+
+        tracing::info!(
+            covers,
+            from_snapshot,
+            rollups = rollups.len(),
+            from_wal = entries.len() - from_snapshot,
+            "restored storage"
+        );
+
+        let next = wal::segments(dir)?
+            .last()
+            .map_or(covers, |seq| seq + 1)
+            .max(covers);
+        let policy = FsyncPolicy::parse(rinha_conf::RINHA_WAL_FSYNC.as_str());
+        let wal = Arc::new(Mutex::new(Wal::create(dir, next, policy)?));
+        let (appends, pending) = mpsc::channel();
+
+        std::thread::Builder::new()
+            .name("rinha-wal".into())
+            .spawn({
+                let wal = wal.clone();
+                move || write_ahead(&wal, pending)
+            })?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            memory: MemoryStorage::with_entries(rollups, entries),
+            wal,
+            appends,
+            maintenance: Mutex::new(()),
+        })
+    }
+
+    /// Rotates the log, snapshots both ledgers as of that rotation and keeps
+    /// the `retain` newest snapshots, dropping the segments they make
+    /// redundant.
+    ///
+    /// `record` inserts before it appends, so whatever an older segment holds
+    /// is already in the ledgers once the rotation is done. The entries are
+    /// then streamed to the file a batch per read lock; one recorded in the
+    /// meantime may land in the snapshot as well as in the new segment,
+    /// which replay tells apart by its correlation id.
+    fn checkpoint(&self, retain: usize) -> Result<(), StorageError> {
+        let _maintenance = self
+            .maintenance
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let covers = self
+            .wal
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .rotate()?;
+
+        let mut writer = snapshot::Writer::create(&self.dir, covers)?;
+        self.memory
+            .scan_entries(|batch| batch.iter().try_for_each(|entry| writer.entry(entry)))?;
+        for rollup in self.memory.rollups() {
+            writer.rollup(&rollup)?;
+        }
+        let (entries, rollups) = writer.finish()?;
+
+        if let Some(oldest) = snapshot::prune(&self.dir, retain)? {
+            wal::prune(&self.dir, oldest)?;
+        }
+
+        tracing::info!(covers, entries, rollups, "wrote snapshot");
+
+        Ok(())
+    }
+}
+
+/// Body of the writer thread: waits for an entry, then appends it along with
+/// every other one pending, up to `GROUP_MAX`, as one group commit. Ends once
+/// the storage is dropped.
+fn write_ahead(wal: &Mutex<Wal>, pending: Receiver<Entry>) {
+    let mut group = Vec::with_capacity(GROUP_MAX);
+
+    while let Ok(entry) = pending.recv() {
+        group.push(entry);
+        group.extend(pending.try_iter().take(GROUP_MAX - 1));
+
+        let res = wal
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .append(&group);
+        if let Err(err) = res {
+            tracing::error!(?err, entries = group.len(), "wal append");
+        }
+
+        group.clear();
+    }
+}
+
+impl Storage for FileStorage {
+    /// Inserts in memory and hands the entry to the writer thread, which
+    /// appends it with whatever else is pending. The insert stands even when
+    /// the append fails: the processor already holds the payment, so the
+    /// writer only reports the error.
+    fn record(&self, target: &UpstreamType, payment: &Payment) -> Result<bool, StorageError> {
+        let entry = Entry::new(target, payment);
+
+        if !self.memory.insert(&entry) {
+            return Ok(false);
+        }
+
+        self.appends.send(entry).map_err(|_| WalError::Stopped)?;
+
+        Ok(true)
+    }
+
+    fn summary(&self, from: i64, to: i64) -> TargetCounter {
+        self.memory.summary(from, to)
+    }
+
+    fn series(&self, bounds: &[i64]) -> Vec<TargetCounter> {
+        self.memory.series(bounds)
+    }
+
+    fn purge(&self) -> Result<(), StorageError> {
+        self.memory.purge()
+    }
+
+    /// Snapshots the ledgers as the only snapshot and drops every older
+    /// segment. An entry recorded since the purge is in the ledgers by the
+    /// time the log rotates, wherever it was appended.
+    fn persist_purge(&self) -> Result<(), StorageError> {
+        self.checkpoint(1)
+    }
+
+    /// Only the in-memory ledgers shrink; the next snapshot persists the
+    /// rollups and lets the log segments behind them be pruned.
+    fn roll_up(&self, before: i64, budget: usize) -> usize {
+        let _maintenance = self
+            .maintenance
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        self.memory.roll_up(before, budget)
+    }
+
+    fn footprint(&self) -> Footprint {
+        self.memory.footprint()
+    }
+
+    fn horizon(&self) -> i64 {
+        self.memory.horizon()
+    }
+
+    fn sync(&self) -> Result<(), StorageError> {
+        let mut wal = self.wal.lock().unwrap_or_else(PoisonError::into_inner);
+
+        Ok(wal.sync()?)
+    }
+
+    /// Bounds the log a restart replays to what was appended since.
+    fn compact(&self) -> Result<(), StorageError> {
+        self.checkpoint(*rinha_conf::RINHA_SNAPSHOT_RETAIN)
+    }
+}
@@ -0,0 +1,157 @@
+use crate::rinha_domain::{Count, Money};
+
+/// Fenwick tree of per-bucket `Count`s over a contiguous span of fixed-width
+/// time buckets, answering the total of any bucket range in `O(log n)`.
+#[derive(Debug)]
+pub struct BucketIndex {
+    width: i64,
+    max_buckets: usize,
+    base: i64,
+    tree: Vec<Count>,
+}
+
+const MIN_BUCKETS: usize = 1024;
+
+impl BucketIndex {
+    pub fn new(width: i64, max_buckets: usize) -> Self {
+        Self {
+            width: width.max(1),
+            max_buckets: max_buckets.max(MIN_BUCKETS),
+            base: 0,
+            tree: vec![Count::default()],
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.tree.len() - 1
+    }
+
+    pub fn bucket(&self, requested_at: i64) -> i64 {
+        requested_at.div_euclid(self.width)
+    }
+
+    pub fn bucket_start(&self, bucket: i64) -> i64 {
+        bucket.saturating_mul(self.width)
+    }
+
+    fn covers(&self, bucket: i64) -> bool {
+        bucket >= self.base && bucket - self.base < self.capacity() as i64
+    }
+
+    /// Adds one payment to its bucket, returning `false` without touching the
+    /// tree when the bucket lies outside the indexed span.
+    pub fn insert(&mut self, requested_at: i64, amount: Money) -> bool {
+        self.update(requested_at, |node| {
+            node.requests += 1;
+            node.amount += amount;
+        })
+    }
+
+    /// Takes back a payment previously inserted, for entries rolled out of
+    /// the ledger; `false` when its bucket lies outside the indexed span.
+    pub fn remove(&mut self, requested_at: i64, amount: Money) -> bool {
+        self.update(requested_at, |node| {
+            node.requests -= 1;
+            node.amount = node.amount - amount;
+        })
+    }
+
+    fn update(&mut self, requested_at: i64, apply: impl Fn(&mut Count)) -> bool {
+        let bucket = self.bucket(requested_at);
+
+        if !self.covers(bucket) {
+            return false;
+        }
+
+        let mut idx = (bucket - self.base) as usize + 1;
+        while idx < self.tree.len() {
+            apply(&mut self.tree[idx]);
+            idx += idx & idx.wrapping_neg();
+        }
+
+        true
+    }
+
+    /// Bytes held by the tree nodes.
+    pub fn footprint(&self) -> usize {
+        self.tree.capacity() * std::mem::size_of::<Count>()
+    }
+
+    /// Drops the span entirely, so the next `grow` anchors a fresh one.
+    pub fn reset(&mut self) {
+        self.base = 0;
+        self.tree = vec![Count::default()];
+    }
+
+    /// Widens the span so it covers the bucket of `requested_at`, clearing
+    /// every node; the caller re-inserts its entries afterwards. Returns
+    /// `false`, leaving the index untouched, when that would take more than
+    /// the configured maximum of buckets.
+    pub fn grow(&mut self, requested_at: i64) -> bool {
+        let bucket = self.bucket(requested_at);
+
+        let (lo, hi, capacity) = if self.capacity() == 0 {
+            // start with room on both sides, most of it ahead in time
+            let capacity = MIN_BUCKETS;
+            let lo = bucket - (capacity / 4) as i64;
+            (lo, lo + capacity as i64, capacity)
+        } else {
+            let lo = bucket.min(self.base);
+            let hi = (bucket + 1).max(self.base + self.capacity() as i64);
+            let Ok(span) = usize::try_from(hi - lo) else {
+                return false;
+            };
+            (lo, hi, span.next_power_of_two().max(self.capacity() * 2))
+        };
+
+        if capacity > self.max_buckets {
+            return false;
+        }
+
+        // grow towards the side that triggered the resize
+        self.base = if bucket < self.base {
+            hi - capacity as i64
+        } else {
+            lo
+        };
+        self.tree = vec![Count::default(); capacity + 1];
+
+        true
+    }
+
+    fn prefix(&self, mut idx: usize) -> Count {
+        let mut count = Count::default();
+
+        while idx > 0 {
+            let node = &self.tree[idx];
+            count.requests += node.requests;
+            count.amount += node.amount;
+            idx &= idx - 1;
+        }
+
+        count
+    }
+
+    /// Totals of the buckets within `lo..=hi` that fall inside the span.
+    pub fn range(&self, lo: i64, hi: i64) -> Count {
+        let lo = lo.max(self.base);
+        let hi = hi.min(self.base + self.capacity() as i64 - 1);
+
+        if lo > hi {
+            return Count::default();
+        }
+
+        let upper = self.prefix((hi - self.base) as usize + 1);
+        let lower = self.prefix((lo - self.base) as usize);
+
+        Count {
+            requests: upper.requests - lower.requests,
+            amount: upper.amount - lower.amount,
+        }
+    }
+
+    /// Whether every bucket within `lo..=hi` lies inside the span.
+    pub fn spans(&self, lo: i64, hi: i64) -> bool {
+        self.covers(lo) && self.covers(hi)
+    }
+}
@@ -0,0 +1,652 @@
+use crate::{
+    rinha_ambulance::UpstreamType,
+    rinha_conf,
+    rinha_domain::{Count, Money, TargetCounter},
+    rinha_storage::{Entry, Footprint, Rollup, index::BucketIndex},
+};
+use std::{
+    collections::{BTreeMap, HashSet, btree_map},
+    ops::Bound,
+};
+use uuid::Uuid;
+
+/// Ledger entries are keyed by `(requested_at, correlation_id)`, so payments
+/// stamped in the same microsecond never overwrite each other.
+pub type LedgerKey = (i64, Uuid);
+
+const RECENTER_MIN: usize = 1024;
+
+// rough per-item heap cost, node overhead and load factor included
+const ENTRY_BYTES: usize = 64;
+const ID_BYTES: usize = 24;
+const ROLLUP_BYTES: usize = 48;
+
+#[derive(Debug)]
+pub struct Ledger {
+    entries: BTreeMap<LedgerKey, Money>,
+    index: BucketIndex,
+    /// entries too far from the rest to fit in the index span
+    strays: BTreeMap<LedgerKey, Money>,
+    /// stray count at which the span is re-centered, doubling every time so
+    /// rebuilds stay rare even when the data never fits
+    recenter_at: usize,
+    /// totals of the entries rolled out of `entries`, keyed by the start of
+    /// their `RINHA_ROLLUP_BUCKET_SECS` bucket
+    rollups: BTreeMap<i64, Count>,
+    rollup_width: i64,
+}
+
+impl Default for Ledger {
+    fn default() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            index: BucketIndex::new(
+                (*rinha_conf::RINHA_INDEX_BUCKET_MS as i64).saturating_mul(1000),
+                *rinha_conf::RINHA_INDEX_MAX_BUCKETS,
+            ),
+            strays: BTreeMap::new(),
+            recenter_at: RECENTER_MIN,
+            rollups: BTreeMap::new(),
+            rollup_width: (*rinha_conf::RINHA_ROLLUP_BUCKET_SECS as i64).saturating_mul(1_000_000),
+        }
+    }
+}
+
+impl Ledger {
+    /// Returns `false` when the exact same `(requested_at, correlation_id)`
+    /// pair was already recorded; the stored amount is left untouched.
+    pub fn insert(&mut self, requested_at: i64, correlation_id: Uuid, amount: Money) -> bool {
+        match self.entries.entry((requested_at, correlation_id)) {
+            btree_map::Entry::Vacant(entry) => {
+                entry.insert(amount);
+            }
+            btree_map::Entry::Occupied(_) => return false,
+        }
+
+        if !self.index.insert(requested_at, amount) {
+            if self.index.grow(requested_at) {
+                self.reindex();
+            } else if self.strays.len() >= self.recenter_at.max(self.entries.len() / 2) {
+                // the span was anchored on outliers, re-center it on this entry
+                self.recenter_at = self.entries.len() * 2;
+                self.index.reset();
+                self.index.grow(requested_at);
+                self.reindex();
+            } else {
+                self.strays.insert((requested_at, correlation_id), amount);
+            }
+        }
+
+        true
+    }
+
+    /// `requested_at` of the oldest entry not rolled up yet.
+    pub fn oldest(&self) -> Option<i64> {
+        self.entries
+            .first_key_value()
+            .map(|((requested_at, _), _)| *requested_at)
+    }
+
+    /// Moves the oldest entry into its rollup bucket, returning its
+    /// correlation id.
+    pub fn roll_up_oldest(&mut self) -> Option<Uuid> {
+        let (key, amount) = self.entries.pop_first()?;
+
+        if self.strays.remove(&key).is_none() {
+            self.index.remove(key.0, amount);
+        }
+
+        let bucket_start = key.0.div_euclid(self.rollup_width) * self.rollup_width;
+        *self.rollups.entry(bucket_start).or_default() += Count {
+            requests: 1,
+            amount,
+        };
+
+        Some(key.1)
+    }
+
+    pub fn insert_rollup(&mut self, bucket_start: i64, count: Count) {
+        *self.rollups.entry(bucket_start).or_default() += count;
+    }
+
+    pub fn rollups(&self) -> impl Iterator<Item = (&i64, &Count)> {
+        self.rollups.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn rollups_len(&self) -> usize {
+        self.rollups.len()
+    }
+
+    /// End of the newest rollup bucket: entries requested before it may have
+    /// been rolled up.
+    pub fn horizon(&self) -> i64 {
+        self.rollups
+            .last_key_value()
+            .map_or(i64::MIN, |(bucket_start, _)| {
+                bucket_start.saturating_add(self.rollup_width)
+            })
+    }
+
+    /// Estimated heap bytes held by entries, strays, rollups and the index.
+    pub fn footprint(&self) -> usize {
+        (self.entries.len() + self.strays.len()) * ENTRY_BYTES
+            + self.rollups.len() * ROLLUP_BYTES
+            + self.index.footprint()
+    }
+
+    fn reindex(&mut self) {
+        self.strays.clear();
+
+        for (key, amount) in &self.entries {
+            if !self.index.insert(key.0, *amount) {
+                self.strays.insert(*key, *amount);
+            }
+        }
+    }
+
+    /// Iterates the entries keyed after `after`, or all of them.
+    pub fn iter_after(
+        &self,
+        after: Option<LedgerKey>,
+    ) -> impl Iterator<Item = (&LedgerKey, &Money)> {
+        let start = match after {
+            Some(key) => Bound::Excluded(key),
+            None => Bound::Unbounded,
+        };
+
+        self.entries.range((start, Bound::Unbounded))
+    }
+
+    /// Iterates every entry whose `requested_at` lies within `from..=to`.
+    pub fn range(&self, from: i64, to: i64) -> impl Iterator<Item = (&LedgerKey, &Money)> {
+        self.entries.range(Self::key_range(from, to))
+    }
+
+    /// Totals of every entry whose `requested_at` lies within `from..=to`.
+    /// Buckets fully inside the window come from the index; only the partial
+    /// buckets at either edge, and strays, are scanned entry by entry.
+    ///
+    /// Rolled-up entries no longer have a timestamp of their own, so a rollup
+    /// bucket counts in full whenever its start lies within `from..=to`: a
+    /// window edge before `horizon` is only as precise as
+    /// `RINHA_ROLLUP_BUCKET_SECS`.
+    pub fn summary(&self, from: i64, to: i64) -> Count {
+        if from > to {
+            return Count::default();
+        }
+
+        let mut count = self.exact_summary(from, to);
+        for (_, rollup) in self.rollups.range(from..=to) {
+            count += *rollup;
+        }
+
+        count
+    }
+
+    /// Totals per bucket `bounds[i]..bounds[i + 1]`, from a single ordered
+    /// pass over the entries and rollups within `bounds[0]..bounds[n]`. Rollups
+    /// count in the bucket their start falls in, as in `summary`.
+    pub fn series(&self, bounds: &[i64]) -> Vec<Count> {
+        let mut counts = vec![Count::default(); bounds.len().saturating_sub(1)];
+
+        let (Some(&lo), Some(&hi)) = (bounds.first(), bounds.last()) else {
+            return counts;
+        };
+        if lo >= hi {
+            return counts;
+        }
+
+        let mut bucket = 0;
+        for ((requested_at, _), amount) in self.range(lo, hi - 1) {
+            while *requested_at >= bounds[bucket + 1] {
+                bucket += 1;
+            }
+            counts[bucket] += Count {
+                requests: 1,
+                amount: *amount,
+            };
+        }
+
+        let mut bucket = 0;
+        for (bucket_start, rollup) in self.rollups.range(lo..hi) {
+            while *bucket_start >= bounds[bucket + 1] {
+                bucket += 1;
+            }
+            counts[bucket] += *rollup;
+        }
+
+        counts
+    }
+
+    fn exact_summary(&self, from: i64, to: i64) -> Count {
+        let mut count = Count::default();
+
+        let (lo, hi) = (self.index.bucket(from), self.index.bucket(to));
+        let first_full = if self.index.bucket_start(lo) == from {
+            lo
+        } else {
+            lo + 1
+        };
+        let last_full = if self.index.bucket_start(hi + 1) - 1 == to {
+            hi
+        } else {
+            hi - 1
+        };
+
+        if first_full > last_full {
+            Self::scan(&mut count, self.range(from, to));
+            return count;
+        }
+
+        let (full_from, full_to) = (
+            self.index.bucket_start(first_full),
+            self.index.bucket_start(last_full + 1) - 1,
+        );
+
+        count = self.index.range(first_full, last_full);
+        Self::scan(&mut count, self.range(from, full_from - 1));
+        Self::scan(&mut count, self.range(full_to + 1, to));
+
+        if !self.index.spans(first_full, last_full) {
+            Self::scan(
+                &mut count,
+                self.strays.range(Self::key_range(full_from, full_to)),
+            );
+        }
+
+        count
+    }
+
+    fn scan<'a>(count: &mut Count, entries: impl Iterator<Item = (&'a LedgerKey, &'a Money)>) {
+        for (_, amount) in entries {
+            count.requests += 1;
+            count.amount += *amount;
+        }
+    }
+
+    fn key_range(from: i64, to: i64) -> (Bound<LedgerKey>, Bound<LedgerKey>) {
+        let start = (from, Uuid::nil());
+
+        if from > to {
+            return (Bound::Included(start), Bound::Excluded(start));
+        }
+
+        (Bound::Included(start), Bound::Included((to, Uuid::max())))
+    }
+}
+
+/// One ledger per processor, behind a single lock so a summary or snapshot
+/// always sees both sides at the same instant. A correlation id is recorded
+/// at most once across both, whatever its `requested_at`.
+#[derive(Debug, Default)]
+pub struct Ledgers {
+    pub default: Ledger,
+    pub fallback: Ledger,
+    ids: HashSet<Uuid>,
+}
+
+impl Ledgers {
+    pub fn get(&self, target: &UpstreamType) -> &Ledger {
+        match target {
+            UpstreamType::Default => &self.default,
+            UpstreamType::Fallback => &self.fallback,
+        }
+    }
+
+    pub fn get_mut(&mut self, target: &UpstreamType) -> &mut Ledger {
+        match target {
+            UpstreamType::Default => &mut self.default,
+            UpstreamType::Fallback => &mut self.fallback,
+        }
+    }
+
+    pub fn insert(&mut self, entry: &Entry) -> bool {
+        if !self.ids.insert(entry.correlation_id) {
+            return false;
+        }
+
+        self.get_mut(&entry.target)
+            .insert(entry.requested_at, entry.correlation_id, entry.amount)
+    }
+
+    pub fn insert_rollup(&mut self, rollup: &Rollup) {
+        self.get_mut(&rollup.target)
+            .insert_rollup(rollup.bucket_start, rollup.count);
+    }
+
+    pub fn contains(&self, entry: &Entry) -> bool {
+        self.ids.contains(&entry.correlation_id)
+    }
+
+    pub fn summary(&self, from: i64, to: i64) -> TargetCounter {
+        TargetCounter {
+            default: self.default.summary(from, to),
+            fallback: self.fallback.summary(from, to),
+        }
+    }
+
+    pub fn series(&self, bounds: &[i64]) -> Vec<TargetCounter> {
+        self.default
+            .series(bounds)
+            .into_iter()
+            .zip(self.fallback.series(bounds))
+            .map(|(default, fallback)| TargetCounter { default, fallback })
+            .collect()
+    }
+
+    /// Instant `roll_up` with `before` and `target` would stop at, rolling up
+    /// every entry requested before it and none after, so that processes
+    /// holding the same entries can all roll up to one agreed boundary.
+    pub fn roll_up_boundary(&self, before: i64, target: usize) -> i64 {
+        let bytes = self.footprint().bytes;
+        if bytes <= target {
+            return before;
+        }
+
+        let excess = (bytes - target).div_ceil(ENTRY_BYTES + ID_BYTES);
+        let mut default = self
+            .default
+            .iter_after(None)
+            .map(|(key, _)| key.0)
+            .peekable();
+        let mut fallback = self
+            .fallback
+            .iter_after(None)
+            .map(|(key, _)| key.0)
+            .peekable();
+        let mut boundary = before;
+
+        for _ in 0..excess {
+            let oldest = match (default.peek(), fallback.peek()) {
+                (Some(d), Some(f)) if f < d => fallback.next(),
+                (Some(_), _) => default.next(),
+                (None, _) => fallback.next(),
+            };
+            let Some(requested_at) = oldest else {
+                break;
+            };
+            boundary = boundary.max(requested_at.saturating_add(1));
+        }
+
+        boundary
+    }
+
+    /// Footprint `roll_up` shrinks the ledgers to: 7/8 of `budget` once over
+    /// it, so the next pass does not start right at the limit.
+    pub fn roll_up_target(&self, budget: usize) -> usize {
+        if self.footprint().bytes > budget {
+            budget / 8 * 7
+        } else {
+            usize::MAX
+        }
+    }
+
+    /// Rolls up to `limit` entries into coarse buckets, oldest first: those
+    /// older than `before`, then more for as long as the ledgers are over
+    /// `target` bytes. Rolled ids are forgotten, which is why payments
+    /// requested before the `horizon` are refused at admission: a late
+    /// duplicate of one would otherwise count twice. Returns how many entries
+    /// were rolled up.
+    pub fn roll_up(&mut self, before: i64, target: usize, limit: usize) -> usize {
+        let mut rolled = 0;
+
+        while rolled < limit {
+            let (oldest, fallback) = match (self.default.oldest(), self.fallback.oldest()) {
+                (Some(default), Some(fallback)) if fallback < default => (fallback, true),
+                (Some(default), _) => (default, false),
+                (None, Some(fallback)) => (fallback, true),
+                (None, None) => break,
+            };
+
+            if oldest >= before && self.footprint().bytes <= target {
+                break;
+            }
+
+            let ledger = if fallback {
+                &mut self.fallback
+            } else {
+                &mut self.default
+            };
+            if let Some(id) = ledger.roll_up_oldest() {
+                self.ids.remove(&id);
+                rolled += 1;
+            }
+        }
+
+        if rolled > 0 && self.ids.len() < self.ids.capacity() / 4 {
+            self.ids.shrink_to_fit();
+        }
+
+        rolled
+    }
+
+    pub fn horizon(&self) -> i64 {
+        self.default.horizon().max(self.fallback.horizon())
+    }
+
+    pub fn footprint(&self) -> Footprint {
+        Footprint {
+            entries: self.default.len() + self.fallback.len(),
+            rollups: self.default.rollups_len() + self.fallback.rollups_len(),
+            bytes: self.default.footprint() + self.fallback.footprint() + self.ids.len() * ID_BYTES,
+            budget: *rinha_conf::RINHA_LEDGER_BUDGET_BYTES,
+        }
+    }
+
+    pub fn rollups(&self) -> impl Iterator<Item = Rollup> {
+        let targets = [
+            (UpstreamType::Default, &self.default),
+            (UpstreamType::Fallback, &self.fallback),
+        ];
+
+        targets.into_iter().flat_map(|(target, ledger)| {
+            ledger.rollups().map(move |(bucket_start, count)| Rollup {
+                target: target.clone(),
+                bucket_start: *bucket_start,
+                count: *count,
+            })
+        })
+    }
+
+    /// Entries of `target`'s ledger keyed after `after`, or all of them.
+    pub fn entries_after(
+        &self,
+        target: &UpstreamType,
+        after: Option<LedgerKey>,
+    ) -> impl Iterator<Item = Entry> {
+        self.get(target)
+            .iter_after(after)
+            .map(move |((requested_at, correlation_id), amount)| Entry {
+                target: target.clone(),
+                requested_at: *requested_at,
+                correlation_id: *correlation_id,
+                amount: *amount,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AT: i64 = 1_750_000_000_000_000;
+
+    fn entry(target: UpstreamType, requested_at: i64, units: i64) -> Entry {
+        Entry {
+            target,
+            requested_at,
+            correlation_id: Uuid::new_v4(),
+            amount: Money::from_units(units),
+        }
+    }
+
+    #[test]
+    fn same_instant_payments_all_count() {
+        let mut ledgers = Ledgers::default();
+        let entries: Vec<_> = (1..=5)
+            .map(|units| entry(UpstreamType::Default, AT, units))
+            .collect();
+
+        for entry in &entries {
+            assert!(ledgers.insert(entry));
+        }
+        assert!(ledgers.insert(&entry(UpstreamType::Fallback, AT, 7)));
+
+        // a window of exactly that microsecond, and one wide enough to be
+        // answered from whole index buckets
+        for (from, to) in [(AT, AT), (AT - 3_600_000_000, AT + 3_600_000_000)] {
+            let summary = ledgers.summary(from, to);
+            assert_eq!(summary.default.requests, 5);
+            assert_eq!(summary.default.amount, Money::from_units(15));
+            assert_eq!(summary.fallback.requests, 1);
+            assert_eq!(summary.fallback.amount, Money::from_units(7));
+
+            assert_eq!(ledgers.default.range(from, to).count(), 5);
+            assert_eq!(ledgers.fallback.range(from, to).count(), 1);
+        }
+    }
+
+    #[test]
+    fn same_instant_duplicate_is_refused() {
+        let mut ledgers = Ledgers::default();
+        let first = entry(UpstreamType::Default, AT, 10);
+
+        assert!(ledgers.insert(&first));
+        assert!(!ledgers.insert(&first));
+        assert!(!ledgers.insert(&Entry {
+            target: UpstreamType::Fallback,
+            amount: Money::from_units(20),
+            ..first.clone()
+        }));
+
+        let summary = ledgers.summary(AT, AT);
+        assert_eq!(summary.default.requests, 1);
+        assert_eq!(summary.default.amount, Money::from_units(10));
+        assert_eq!(summary.fallback.requests, 0);
+    }
+
+    #[test]
+    fn horizon_covers_rolled_up_entries() {
+        let mut ledgers = Ledgers::default();
+        assert_eq!(ledgers.horizon(), i64::MIN);
+
+        let old = entry(UpstreamType::Default, AT, 1);
+        ledgers.insert(&old);
+        ledgers.insert(&entry(UpstreamType::Fallback, AT + 3_600_000_000, 2));
+
+        assert_eq!(ledgers.roll_up(AT + 1, usize::MAX, usize::MAX), 1);
+        assert!(ledgers.horizon() > old.requested_at);
+        assert!(ledgers.horizon() <= AT + 3_600_000_000);
+
+        // the id is forgotten, only the horizon still keeps it out
+        assert!(!ledgers.contains(&old));
+
+        // rolled up, it counts by the start of its bucket instead
+        let bucket = *rinha_conf::RINHA_ROLLUP_BUCKET_SECS as i64 * 1_000_000;
+        assert_eq!(ledgers.summary(AT, AT).default.requests, 0);
+        assert_eq!(ledgers.summary(AT - bucket, AT).default.requests, 1);
+    }
+
+    /// xorshift64*, so the randomized comparison is reproducible.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        fn within(&mut self, lo: i64, hi: i64) -> i64 {
+            lo + (self.next() % (hi - lo + 1) as u64) as i64
+        }
+    }
+
+    fn brute_force(ledger: &Ledger, from: i64, to: i64) -> Count {
+        let mut count = Count::default();
+        for ((requested_at, _), amount) in &ledger.entries {
+            if (from..=to).contains(requested_at) {
+                count.requests += 1;
+                count.amount += *amount;
+            }
+        }
+
+        count
+    }
+
+    /// An instant the index treats specially as often as a random one: an
+    /// entry's own, a bucket edge, or either's neighbour.
+    fn instant(rng: &mut Rng, ledger: &Ledger, lo: i64, hi: i64) -> i64 {
+        let width = ledger.index.bucket_start(1);
+        let keys: Vec<i64> = ledger.entries.keys().map(|(at, _)| *at).collect();
+        let near = keys[rng.next() as usize % keys.len()];
+
+        match rng.next() % 5 {
+            0 => near,
+            1 => near.div_euclid(width) * width,
+            2 => near.div_euclid(width) * width - 1,
+            3 => near + rng.within(-1, 1),
+            _ => rng.within(lo, hi),
+        }
+    }
+
+    fn assert_matches_brute_force(rng: &mut Rng, ledger: &Ledger, lo: i64, hi: i64) {
+        for _ in 0..300 {
+            let (a, b) = (instant(rng, ledger, lo, hi), instant(rng, ledger, lo, hi));
+            let (from, to) = (a.min(b), a.max(b));
+
+            let expected = brute_force(ledger, from, to);
+            let summary = ledger.summary(from, to);
+            assert_eq!(summary.requests, expected.requests, "{from}..={to}");
+            assert_eq!(summary.amount, expected.amount, "{from}..={to}");
+        }
+    }
+
+    #[test]
+    fn index_summary_matches_a_full_scan() {
+        const HOUR: i64 = 3_600_000_000;
+        const DAY: i64 = 24 * HOUR;
+
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        let mut ledger = Ledger::default();
+        let far = AT + 60 * DAY;
+        let insert = |ledger: &mut Ledger, rng: &mut Rng, lo: i64, hi: i64| {
+            let requested_at = rng.within(lo, hi);
+            let amount = Money::from_units(rng.within(1, 10_000));
+            assert!(ledger.insert(requested_at, Uuid::new_v4(), amount));
+        };
+
+        // hours on either side of the first entry make the span grow
+        for _ in 0..500 {
+            insert(&mut ledger, &mut rng, AT - 2 * HOUR, AT + 2 * HOUR);
+        }
+        assert!(ledger.strays.is_empty());
+        assert_matches_brute_force(&mut rng, &ledger, AT - 3 * HOUR, AT + 3 * HOUR);
+
+        // too far to fit in the span, they pile up as strays
+        for _ in 0..600 {
+            insert(&mut ledger, &mut rng, far - HOUR, far + HOUR);
+        }
+        assert!(!ledger.strays.is_empty());
+        assert_matches_brute_force(&mut rng, &ledger, AT - 3 * HOUR, far + 3 * HOUR);
+
+        // until there are enough of them for the span to re-center on them
+        for _ in 0..600 {
+            insert(&mut ledger, &mut rng, far - HOUR, far + HOUR);
+        }
+        assert!(ledger.recenter_at > RECENTER_MIN);
+        assert_matches_brute_force(&mut rng, &ledger, AT - 3 * HOUR, far + 3 * HOUR);
+
+        // entries rolled out are taken back out of the index
+        for _ in 0..300 {
+            ledger.roll_up_oldest();
+        }
+        ledger.rollups.clear();
+        assert_matches_brute_force(&mut rng, &ledger, AT - 3 * HOUR, far + 3 * HOUR);
+    }
+}
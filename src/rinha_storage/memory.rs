@@ -0,0 +1,129 @@
+use crate::{
+    rinha_ambulance::UpstreamType,
+    rinha_domain::{Payment, TargetCounter},
+    rinha_storage::{Entry, Footprint, Rollup, Storage, StorageError, ledger::Ledgers},
+};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Entries rolled up per write lock, or copied out per read lock by
+/// `scan_entries`, so `record` on the runtime threads never waits on a whole
+/// pass over the ledgers.
+const BATCH: usize = 4096;
+
+/// Process-local ledgers; everything is lost when the process exits.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    ledgers: RwLock<Ledgers>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_entries(
+        rollups: impl IntoIterator<Item = Rollup>,
+        entries: impl IntoIterator<Item = Entry>,
+    ) -> Self {
+        let mut ledgers = Ledgers::default();
+
+        for rollup in rollups {
+            ledgers.insert_rollup(&rollup);
+        }
+        for entry in entries {
+            ledgers.insert(&entry);
+        }
+
+        Self {
+            ledgers: RwLock::new(ledgers),
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Ledgers> {
+        self.ledgers.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Ledgers> {
+        self.ledgers.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns `false` when the entry's correlation id was already recorded.
+    pub fn insert(&self, entry: &Entry) -> bool {
+        self.write().insert(entry)
+    }
+
+    /// Hands every entry to `visit`, `BATCH` at a time, each batch copied
+    /// out under a read lock of its own. Entries inserted meanwhile may or
+    /// may not be visited; none is visited twice.
+    pub fn scan_entries<E>(
+        &self,
+        mut visit: impl FnMut(&[Entry]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        for target in [UpstreamType::Default, UpstreamType::Fallback] {
+            let mut after = None;
+
+            loop {
+                let batch: Vec<Entry> = self
+                    .read()
+                    .entries_after(&target, after)
+                    .take(BATCH)
+                    .collect();
+
+                let Some(last) = batch.last() else {
+                    break;
+                };
+                after = Some((last.requested_at, last.correlation_id));
+
+                visit(&batch)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn rollups(&self) -> Vec<Rollup> {
+        self.read().rollups().collect()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn record(&self, target: &UpstreamType, payment: &Payment) -> Result<bool, StorageError> {
+        Ok(self.insert(&Entry::new(target, payment)))
+    }
+
+    fn summary(&self, from: i64, to: i64) -> TargetCounter {
+        self.read().summary(from, to)
+    }
+
+    fn series(&self, bounds: &[i64]) -> Vec<TargetCounter> {
+        self.read().series(bounds)
+    }
+
+    fn purge(&self) -> Result<(), StorageError> {
+        *self.write() = Ledgers::default();
+
+        Ok(())
+    }
+
+    fn roll_up(&self, before: i64, budget: usize) -> usize {
+        let target = self.read().roll_up_target(budget);
+        let mut rolled = 0;
+
+        loop {
+            let batch = self.write().roll_up(before, target, BATCH);
+            rolled += batch;
+
+            if batch < BATCH {
+                return rolled;
+            }
+        }
+    }
+
+    fn footprint(&self) -> Footprint {
+        self.read().footprint()
+    }
+
+    fn horizon(&self) -> i64 {
+        self.read().horizon()
+    }
+}
@@ -1,25 +1,279 @@
+use crate::{
+    rinha_ambulance::UpstreamType,
+    rinha_conf,
+    rinha_domain::{Count, Money, Payment, TargetCounter, dt_to_i64},
+};
+use chrono::Utc;
+use serde::Serialize;
 use std::{
-    collections::BTreeMap,
-    sync::{Arc, LazyLock},
+    path::Path,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicI64, Ordering},
+    },
 };
-use tokio::sync::RwLock;
+use tokio::time::{Duration, interval};
+use uuid::Uuid;
+
+mod file;
+mod index;
+mod ledger;
+mod memory;
+mod shm;
+mod snapshot;
+mod wal;
+
+#[cfg(test)]
+mod testing {
+    use std::{fs, path::PathBuf};
+    use uuid::Uuid;
+
+    /// A fresh directory under the system temp dir, removed on drop.
+    pub struct TempDir(pub PathBuf);
+
+    impl TempDir {
+        pub fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("rinha-{}", Uuid::new_v4()));
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+}
+
+pub const INTERNAL_STORAGE_PATH: &str = "/internal/storage";
+
+/// A payment as recorded in the ledger of the processor that accepted it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub target: UpstreamType,
+    pub requested_at: i64,
+    pub correlation_id: Uuid,
+    pub amount: Money,
+}
+
+impl Entry {
+    pub fn new(target: &UpstreamType, payment: &Payment) -> Self {
+        Self {
+            target: target.clone(),
+            requested_at: dt_to_i64(payment.requested_at),
+            correlation_id: payment.correlation_id,
+            amount: payment.amount,
+        }
+    }
+}
 
-pub type Storage = BTreeMap<i64, f64>;
+/// Totals of the entries rolled up into the `RINHA_ROLLUP_BUCKET_SECS` bucket
+/// starting at `bucket_start`.
+#[derive(Debug, Clone)]
+pub struct Rollup {
+    pub target: UpstreamType,
+    pub bucket_start: i64,
+    pub count: Count,
+}
 
-static DEFAULT_STORAGE: LazyLock<Arc<RwLock<Storage>>> =
-    LazyLock::new(|| Arc::new(RwLock::new(Storage::new())));
-static FALLBACK_STORAGE: LazyLock<Arc<RwLock<Storage>>> =
-    LazyLock::new(|| Arc::new(RwLock::new(Storage::new())));
+/// Estimated heap usage of the ledgers, checked against
+/// `RINHA_LEDGER_BUDGET_BYTES` every `RINHA_RETENTION_INTERVAL_SECS`.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct Footprint {
+    pub entries: usize,
+    pub rollups: usize,
+    pub bytes: usize,
+    #[serde(rename = "budgetBytes")]
+    pub budget: usize,
+}
 
-pub fn bootstrap() {
-    LazyLock::force(&DEFAULT_STORAGE);
-    LazyLock::force(&FALLBACK_STORAGE);
+#[derive(thiserror::Error, Debug)]
+pub enum StorageError {
+    #[error("io")]
+    IO(#[from] std::io::Error),
+    #[error("wal")]
+    Wal(#[from] wal::WalError),
+    #[error("snapshot")]
+    Snapshot(#[from] snapshot::SnapshotError),
+    #[error("shm")]
+    Shm(#[from] shm::ShmError),
 }
 
-pub fn get_default_storage() -> Arc<RwLock<Storage>> {
-    DEFAULT_STORAGE.clone()
+/// Ledger of processed payments the worker and HTTP handlers program against,
+/// whichever backend `RINHA_STORAGE` selects.
+pub trait Storage: Send + Sync {
+    /// Records a payment the `target` processor accepted, returning `false`
+    /// when that exact entry was already recorded.
+    fn record(&self, target: &UpstreamType, payment: &Payment) -> Result<bool, StorageError>;
+
+    /// Totals per processor of the entries whose `requested_at` lies within
+    /// `from..=to`, both in microseconds.
+    fn summary(&self, from: i64, to: i64) -> TargetCounter;
+
+    /// Totals per processor of every bucket `bounds[i]..bounds[i + 1]`, in
+    /// microseconds, start inclusive and end exclusive; `bounds` is sorted.
+    fn series(&self, bounds: &[i64]) -> Vec<TargetCounter>;
+
+    /// Drops every recorded entry and rollup from memory. Runs while no
+    /// payment can be recorded, so it never touches the disk.
+    fn purge(&self) -> Result<(), StorageError>;
+
+    /// Makes the last `purge` survive a restart; payments recorded since are
+    /// kept.
+    fn persist_purge(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    /// Rolls entries older than `before`, and then the oldest ones for as
+    /// long as the ledgers exceed `budget` bytes, up into coarse buckets that
+    /// still answer summaries. Returns how many entries were rolled up.
+    fn roll_up(&self, before: i64, budget: usize) -> usize;
+
+    fn footprint(&self) -> Footprint;
+
+    /// Instant before which entries may have been rolled up, and their
+    /// correlation ids forgotten.
+    fn horizon(&self) -> i64 {
+        i64::MIN
+    }
+
+    /// Whether `record` would refuse any new entry for lack of room.
+    fn is_full(&self) -> bool {
+        false
+    }
+
+    /// Flushes pending writes, called every `RINHA_WAL_FSYNC_INTERVAL_MS`.
+    fn sync(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    /// Bounds startup work, called every `RINHA_SNAPSHOT_INTERVAL_SECS`.
+    fn compact(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
 }
 
-pub fn get_fallback_storage() -> Arc<RwLock<Storage>> {
-    FALLBACK_STORAGE.clone()
+static STORAGE: OnceLock<Arc<dyn Storage>> = OnceLock::new();
+/// `Storage::horizon` as of the last roll-up, read on every admission
+static HORIZON: AtomicI64 = AtomicI64::new(i64::MIN);
+
+#[derive(thiserror::Error, Debug)]
+pub enum BootstrapError {
+    #[error("storage")]
+    Storage(#[from] StorageError),
+    #[error("unknown backend {0}")]
+    UnknownBackend(String),
+    #[error("already bootstrapped")]
+    AlreadyBootstrapped,
+}
+
+pub fn bootstrap() -> Result<(), BootstrapError> {
+    let storage: Arc<dyn Storage> = match rinha_conf::RINHA_STORAGE.as_str() {
+        "memory" => Arc::new(memory::MemoryStorage::new()),
+        "file" => Arc::new(file::FileStorage::open(Path::new(
+            rinha_conf::RINHA_DATA_DIR.as_str(),
+        ))?),
+        "shm" => Arc::new(
+            shm::ShmStorage::open(
+                Path::new(rinha_conf::RINHA_SHM_PATH.as_str()),
+                *rinha_conf::RINHA_SHM_CAPACITY,
+            )
+            .map_err(StorageError::from)?,
+        ),
+        backend => return Err(BootstrapError::UnknownBackend(backend.into())),
+    };
+
+    HORIZON.store(storage.horizon(), Ordering::Relaxed);
+
+    STORAGE
+        .set(storage)
+        .map_err(|_| BootstrapError::AlreadyBootstrapped)
+}
+
+pub fn get_storage() -> Arc<dyn Storage> {
+    STORAGE.get().expect("storage not bootstrapped").clone()
+}
+
+/// Payments requested before this instant are refused: a duplicate of one
+/// already rolled up could no longer be told apart.
+pub fn horizon() -> i64 {
+    HORIZON.load(Ordering::Relaxed)
+}
+
+pub fn purge() -> Result<(), StorageError> {
+    get_storage().purge()?;
+    HORIZON.store(i64::MIN, Ordering::Relaxed);
+
+    Ok(())
+}
+
+pub fn persist_purge() -> Result<(), StorageError> {
+    get_storage().persist_purge()
+}
+
+/// Applies `RINHA_RETENTION_SECS` and `RINHA_LEDGER_BUDGET_BYTES`, either of
+/// them disabled when zero.
+fn retain(storage: &dyn Storage) {
+    let before = match *rinha_conf::RINHA_RETENTION_SECS {
+        0 => i64::MIN,
+        secs => dt_to_i64(Utc::now()).saturating_sub((secs as i64).saturating_mul(1_000_000)),
+    };
+    let budget = match *rinha_conf::RINHA_LEDGER_BUDGET_BYTES {
+        0 => usize::MAX,
+        budget => budget,
+    };
+
+    let rolled = storage.roll_up(before, budget);
+    HORIZON.store(storage.horizon(), Ordering::Relaxed);
+    let footprint = storage.footprint();
+
+    if rolled > 0 {
+        tracing::info!(rolled, ?footprint, "rolled up ledger entries");
+    } else {
+        tracing::debug!(?footprint, "ledger footprint");
+    }
+}
+
+pub async fn task() {
+    let mut fsync_ticker = interval(Duration::from_millis(
+        *rinha_conf::RINHA_WAL_FSYNC_INTERVAL_MS,
+    ));
+    let mut snapshot_ticker = interval(Duration::from_secs(
+        (*rinha_conf::RINHA_SNAPSHOT_INTERVAL_SECS).max(1),
+    ));
+    snapshot_ticker.tick().await;
+    let mut retention_ticker = interval(Duration::from_secs(
+        *rinha_conf::RINHA_RETENTION_INTERVAL_SECS,
+    ));
+
+    loop {
+        let storage = get_storage();
+
+        // each may block on disk or a long roll-up, so all of them run on the
+        // blocking pool; `record` and the summaries still share the ledger
+        // lock with them from the runtime threads, which is why they only
+        // ever hold it briefly
+        let res = tokio::select! {
+            _ = fsync_ticker.tick() => {
+                tokio::task::spawn_blocking(move || storage.sync()).await
+            }
+            _ = snapshot_ticker.tick(), if *rinha_conf::RINHA_SNAPSHOT_INTERVAL_SECS > 0 => {
+                tokio::task::spawn_blocking(move || storage.compact()).await
+            }
+            _ = retention_ticker.tick() => {
+                tokio::task::spawn_blocking(move || {
+                    retain(storage.as_ref());
+                    Ok(())
+                })
+                .await
+            }
+        };
+
+        match res {
+            Ok(Err(err)) => tracing::error!(?err, "storage task"),
+            Err(err) => tracing::error!(?err, "storage task"),
+            Ok(Ok(())) => {}
+        }
+    }
 }
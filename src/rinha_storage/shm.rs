@@ -0,0 +1,454 @@
+use crate::{
+    rinha_ambulance::UpstreamType,
+    rinha_conf,
+    rinha_domain::{Money, Payment, TargetCounter},
+    rinha_storage::{Entry, Footprint, Storage, StorageError, ledger::Ledgers},
+};
+use memmap2::MmapMut;
+use std::{
+    fs::OpenOptions,
+    mem::size_of,
+    path::Path,
+    sync::{
+        Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicI64, AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+const MAGIC: u64 = u64::from_le_bytes(*b"RSHM0001");
+
+/// How long a reserved but uncommitted slot may hold readers back before it
+/// is assumed to belong to a writer that died between reserving and committing.
+const STALL_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Set in `commit` while a writer fills the slot in.
+const WRITING: u64 = 1 << 63;
+
+/// Shared header at offset 0. A zero-filled file is a valid empty ledger, so
+/// whichever process maps it first only has to stamp `magic`, `money_scale`
+/// and `capacity`.
+#[repr(C)]
+struct Header {
+    magic: AtomicU64,
+    capacity: AtomicU64,
+    /// slots reserved so far, may run past `capacity` when the file is full
+    len: AtomicU64,
+    /// bumped by every purge; only slots committed under the current
+    /// generation are part of the ledger
+    generation: AtomicU64,
+    /// `RINHA_MONEY_SCALE` the amounts were written with
+    money_scale: AtomicU64,
+    /// entries requested before this are rolled up by every process; zero,
+    /// as in a fresh file, for none yet
+    rolled_up_before: AtomicI64,
+    _reserved: [AtomicU64; 2],
+}
+
+/// Fields are atomics so concurrent access across processes is well-defined.
+/// A writer first claims the slot by setting `commit` to
+/// `(generation + 1) | WRITING`, and publishes it last, with release ordering,
+/// as `generation + 1`.
+#[repr(C)]
+struct Slot {
+    commit: AtomicU64,
+    target: AtomicU64,
+    requested_at: AtomicI64,
+    correlation_id: [AtomicU64; 2],
+    amount: AtomicI64,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ShmError {
+    #[error("io")]
+    IO(#[from] std::io::Error),
+    #[error("bad magic")]
+    BadMagic,
+    #[error("capacity mismatch, file holds {0} slots")]
+    CapacityMismatch(u64),
+    #[error("money scale mismatch, file uses {0}")]
+    ScaleMismatch(u64),
+    #[error("full")]
+    Full,
+    #[error("purged while appending")]
+    Purged,
+}
+
+/// What this process has folded from the shared slots so far.
+#[derive(Debug, Default)]
+struct Local {
+    ledgers: Ledgers,
+    generation: u64,
+    cursor: u64,
+    stalled_since: Option<Instant>,
+    /// slots behind `cursor` passed over while uncommitted, checked again on
+    /// every catch-up in case their writer was only slow
+    skipped: Vec<u64>,
+}
+
+/// Append-only ledger in a memory-mapped file (on `/dev/shm` or a shared
+/// volume) that every instance appends to. Each process folds the slots into
+/// its own ledgers and index, so summaries report the global totals.
+#[derive(Debug)]
+pub struct ShmStorage {
+    mmap: MmapMut,
+    capacity: u64,
+    local: Mutex<Local>,
+}
+
+impl ShmStorage {
+    pub fn open(path: &Path, capacity: u64) -> Result<Self, ShmError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let size = (size_of::<Header>() + capacity as usize * size_of::<Slot>()) as u64;
+        if file.metadata()?.len() < size {
+            file.set_len(size)?;
+        }
+
+        // SAFETY: the file is only ever accessed through the atomics of
+        // `Header` and `Slot`, which is sound under concurrent mutation
+        let mmap = unsafe { MmapMut::map_mut(&file)? };
+        let storage = Self {
+            mmap,
+            capacity,
+            local: Mutex::new(Local::default()),
+        };
+
+        let header = storage.header();
+        match header
+            .magic
+            .compare_exchange(0, MAGIC, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => {
+                header
+                    .money_scale
+                    .store(*rinha_conf::RINHA_MONEY_SCALE as u64, Ordering::Relaxed);
+                header.capacity.store(capacity, Ordering::Release);
+            }
+            Err(MAGIC) => {}
+            Err(_) => return Err(ShmError::BadMagic),
+        }
+
+        // a racing creator may not have stamped the capacity just yet
+        let started = Instant::now();
+        loop {
+            match header.capacity.load(Ordering::Acquire) {
+                0 if started.elapsed() < STALL_TIMEOUT => std::thread::yield_now(),
+                stored if stored == capacity => break,
+                stored => return Err(ShmError::CapacityMismatch(stored)),
+            }
+        }
+
+        match header.money_scale.load(Ordering::Relaxed) {
+            scale if scale == *rinha_conf::RINHA_MONEY_SCALE as u64 => {}
+            scale => return Err(ShmError::ScaleMismatch(scale)),
+        }
+
+        Ok(storage)
+    }
+
+    fn header(&self) -> &Header {
+        // SAFETY: the mapping is page aligned and at least `size_of::<Header>()` long
+        unsafe { &*(self.mmap.as_ptr() as *const Header) }
+    }
+
+    fn slot(&self, idx: u64) -> &Slot {
+        debug_assert!(idx < self.capacity);
+
+        // SAFETY: `idx < capacity` and the mapping holds `capacity` slots
+        // right after the header, which keeps them 8-byte aligned
+        unsafe {
+            let slots = self.mmap.as_ptr().add(size_of::<Header>()) as *const Slot;
+            &*slots.add(idx as usize)
+        }
+    }
+
+    /// Claims `slot` for a writer of `generation`, waiting out one that is
+    /// still filling it in, or taking it over once that one stalled. Returns
+    /// `false` when the slot was already committed under `generation`, which
+    /// only happens to a writer that reserved it across a purge.
+    fn claim(slot: &Slot, generation: u64) -> bool {
+        let started = Instant::now();
+        let mut current = slot.commit.load(Ordering::Acquire);
+
+        loop {
+            if current == generation + 1 {
+                return false;
+            }
+            if current & WRITING != 0 && started.elapsed() < STALL_TIMEOUT {
+                std::thread::yield_now();
+                current = slot.commit.load(Ordering::Acquire);
+                continue;
+            }
+
+            match slot.commit.compare_exchange_weak(
+                current,
+                (generation + 1) | WRITING,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return true,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    fn append(&self, entry: &Entry) -> Result<(), ShmError> {
+        let header = self.header();
+        let generation = header.generation.load(Ordering::Acquire);
+
+        let slot = loop {
+            let idx = header.len.fetch_add(1, Ordering::AcqRel);
+            if idx >= self.capacity {
+                return Err(ShmError::Full);
+            }
+
+            let slot = self.slot(idx);
+            if Self::claim(slot, generation) {
+                break slot;
+            }
+        };
+
+        let (hi, lo) = entry.correlation_id.as_u64_pair();
+        slot.target.store(
+            match entry.target {
+                UpstreamType::Default => 0,
+                UpstreamType::Fallback => 1,
+            },
+            Ordering::Relaxed,
+        );
+        slot.requested_at
+            .store(entry.requested_at, Ordering::Relaxed);
+        slot.correlation_id[0].store(hi, Ordering::Relaxed);
+        slot.correlation_id[1].store(lo, Ordering::Relaxed);
+        slot.amount.store(entry.amount.units(), Ordering::Relaxed);
+
+        // committed under a generation a purge already ended, the entry would
+        // silently never show up
+        if header.generation.load(Ordering::Acquire) != generation {
+            slot.commit.store(0, Ordering::Release);
+            return Err(ShmError::Purged);
+        }
+        slot.commit.store(generation + 1, Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Folds slot `idx` into `ledgers` if it is committed under `generation`.
+    fn fold(&self, ledgers: &mut Ledgers, idx: u64, generation: u64) -> bool {
+        let slot = self.slot(idx);
+
+        if slot.commit.load(Ordering::Acquire) != generation + 1 {
+            return false;
+        }
+
+        let entry = Entry {
+            target: match slot.target.load(Ordering::Relaxed) {
+                0 => UpstreamType::Default,
+                _ => UpstreamType::Fallback,
+            },
+            requested_at: slot.requested_at.load(Ordering::Relaxed),
+            correlation_id: Uuid::from_u64_pair(
+                slot.correlation_id[0].load(Ordering::Relaxed),
+                slot.correlation_id[1].load(Ordering::Relaxed),
+            ),
+            amount: Money::from_units(slot.amount.load(Ordering::Relaxed)),
+        };
+        ledgers.insert(&entry);
+
+        true
+    }
+
+    /// Folds every slot committed since the last call into the local
+    /// ledgers, starting over when another process purged in the meantime.
+    fn catch_up(&self, local: &mut Local) {
+        let header = self.header();
+        let generation = header.generation.load(Ordering::Acquire);
+
+        if generation != local.generation {
+            *local = Local {
+                generation,
+                ..Local::default()
+            };
+        }
+
+        let Local {
+            ledgers, skipped, ..
+        } = local;
+        skipped.retain(|idx| !self.fold(ledgers, *idx, generation));
+
+        let len = header.len.load(Ordering::Acquire).min(self.capacity);
+
+        while local.cursor < len {
+            if !self.fold(&mut local.ledgers, local.cursor, generation) {
+                let stalled_since = *local.stalled_since.get_or_insert_with(Instant::now);
+                if stalled_since.elapsed() < STALL_TIMEOUT {
+                    break;
+                }
+
+                tracing::warn!(slot = local.cursor, "passing over uncommitted shm slot");
+                local.skipped.push(local.cursor);
+            }
+
+            local.cursor += 1;
+            local.stalled_since = None;
+        }
+
+        if local.cursor >= len {
+            local.stalled_since = None;
+        }
+    }
+
+    fn rolled_up_before(&self) -> i64 {
+        match self.header().rolled_up_before.load(Ordering::Acquire) {
+            0 => i64::MIN,
+            before => before,
+        }
+    }
+
+    fn local(&self) -> MutexGuard<'_, Local> {
+        let mut local = self.local.lock().unwrap_or_else(PoisonError::into_inner);
+        self.catch_up(&mut local);
+        local
+    }
+}
+
+impl Storage for ShmStorage {
+    fn record(&self, target: &UpstreamType, payment: &Payment) -> Result<bool, StorageError> {
+        let entry = Entry::new(target, payment);
+        let mut local = self.local();
+
+        if local.ledgers.contains(&entry) {
+            return Ok(false);
+        }
+
+        self.append(&entry)?;
+        self.catch_up(&mut local);
+
+        Ok(true)
+    }
+
+    fn summary(&self, from: i64, to: i64) -> TargetCounter {
+        self.local().ledgers.summary(from, to)
+    }
+
+    fn series(&self, bounds: &[i64]) -> Vec<TargetCounter> {
+        self.local().ledgers.series(bounds)
+    }
+
+    /// Empties the ledger for every attached process. An append racing with
+    /// it fails with `Purged` unless it committed first, in which case it is
+    /// dropped along with the purged entries.
+    fn purge(&self) -> Result<(), StorageError> {
+        let header = self.header();
+
+        header.rolled_up_before.store(0, Ordering::Release);
+        header.generation.fetch_add(1, Ordering::AcqRel);
+        header.len.store(0, Ordering::Release);
+
+        // resets this process right away, the others on their next access
+        drop(self.local());
+
+        Ok(())
+    }
+
+    /// Pushes the shared boundary to what this process needs, then rolls up
+    /// its folded ledgers against the shared one: each process holds the
+    /// same entries, so they all roll up the same ones whatever their own
+    /// budget pressure. The shared slots stay as they are, bounded by
+    /// `RINHA_SHM_CAPACITY`.
+    fn roll_up(&self, before: i64, budget: usize) -> usize {
+        let mut local = self.local();
+        let target = local.ledgers.roll_up_target(budget);
+        let boundary = local.ledgers.roll_up_boundary(before, target);
+
+        if boundary > i64::MIN {
+            self.header()
+                .rolled_up_before
+                .fetch_max(boundary, Ordering::AcqRel);
+        }
+
+        local
+            .ledgers
+            .roll_up(self.rolled_up_before(), usize::MAX, usize::MAX)
+    }
+
+    fn footprint(&self) -> Footprint {
+        self.local().ledgers.footprint()
+    }
+
+    /// Also covers the shared boundary, which other processes may already
+    /// have rolled up to.
+    fn horizon(&self) -> i64 {
+        self.local().ledgers.horizon().max(self.rolled_up_before())
+    }
+
+    fn is_full(&self) -> bool {
+        self.header().len.load(Ordering::Acquire) >= self.capacity
+    }
+
+    fn sync(&self) -> Result<(), StorageError> {
+        Ok(self.mmap.flush_async()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rinha_storage::testing::TempDir;
+    use chrono::DateTime;
+
+    const AT: i64 = 1_750_000_000_000_000;
+
+    fn payment(idx: i64) -> Payment {
+        Payment {
+            correlation_id: Uuid::new_v4(),
+            amount: Money::from_units(100 + idx),
+            requested_at: DateTime::from_timestamp_micros(AT + idx * 1_000_000).unwrap(),
+        }
+    }
+
+    #[test]
+    fn processes_roll_up_to_the_shared_boundary() {
+        let dir = TempDir::new();
+        let path = dir.0.join("ledger.shm");
+        let a = ShmStorage::open(&path, 1024).unwrap();
+        let b = ShmStorage::open(&path, 1024).unwrap();
+
+        for idx in 0..400 {
+            let (storage, target) = match idx % 2 {
+                0 => (&a, UpstreamType::Default),
+                _ => (&b, UpstreamType::Fallback),
+            };
+            assert!(storage.record(&target, &payment(idx)).unwrap());
+        }
+
+        // only `a` is over its budget, `b` rolls up as far as `a` went anyway
+        let budget = a.footprint().bytes / 2;
+        assert!(a.roll_up(i64::MIN, budget) > 0);
+        assert!(b.roll_up(i64::MIN, usize::MAX) > 0);
+
+        assert_eq!(a.horizon(), b.horizon());
+        assert_eq!(a.footprint().entries, b.footprint().entries);
+
+        let bounds = [AT - 3_600_000_000, AT + 120_000_000, AT + 3_600_000_000];
+        for (a, b) in a.series(&bounds).iter().zip(b.series(&bounds)) {
+            for (a, b) in [(a.default, b.default), (a.fallback, b.fallback)] {
+                assert_eq!(a.requests, b.requests);
+                assert_eq!(a.amount, b.amount);
+            }
+        }
+
+        a.purge().unwrap();
+        assert_eq!(b.horizon(), i64::MIN);
+    }
+}
@@ -0,0 +1,475 @@
+use crate::{
+    rinha_ambulance::UpstreamType,
+    rinha_conf,
+    rinha_domain::{Count, Money},
+    rinha_storage::{Entry, Rollup, wal::RECORD_SIZE},
+};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+/// `magic (4) | covers (8) | count (8) | rollup count (8) | money scale (4)
+/// | entries (count * RECORD_SIZE) | rollups (rollup count * ROLLUP_SIZE) | crc32 (4)`,
+/// integers little-endian; the trailing checksum covers every byte before it.
+const MAGIC: &[u8; 4] = b"RSN3";
+const HEADER_SIZE: usize = 4 + 8 + 8 + 8 + 4;
+
+/// Snapshots written before the money scale was recorded.
+const MAGIC_V2: &[u8; 4] = b"RSN2";
+const HEADER_SIZE_V2: usize = 4 + 8 + 8 + 8;
+
+/// Snapshots written before rollups existed: no rollup count, no rollups.
+const MAGIC_V1: &[u8; 4] = b"RSN1";
+const HEADER_SIZE_V1: usize = 4 + 8 + 8;
+
+/// `target (1) | bucket start (8) | requests (8) | amount (8)`
+const ROLLUP_SIZE: usize = 1 + 8 + 8 + 8;
+
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+const SNAPSHOT_SUFFIX: &str = ".snap";
+
+#[derive(thiserror::Error, Debug)]
+pub enum SnapshotError {
+    #[error("io")]
+    IO(#[from] std::io::Error),
+    #[error("money scale mismatch, snapshot {covers} uses {scale}")]
+    ScaleMismatch { covers: u64, scale: u32 },
+}
+
+/// A snapshot holds the full ledger state as of the moment the wal rotated
+/// to segment `covers`, so only segments numbered `covers` onwards need to be
+/// replayed on top of it.
+#[derive(Debug)]
+pub struct Snapshot {
+    pub covers: u64,
+    pub entries: Vec<Entry>,
+    pub rollups: Vec<Rollup>,
+}
+
+impl Rollup {
+    fn encode(&self) -> [u8; ROLLUP_SIZE] {
+        let mut buf = [0u8; ROLLUP_SIZE];
+
+        buf[0] = match self.target {
+            UpstreamType::Default => 0,
+            UpstreamType::Fallback => 1,
+        };
+        buf[1..9].copy_from_slice(&self.bucket_start.to_le_bytes());
+        buf[9..17].copy_from_slice(&self.count.requests.to_le_bytes());
+        buf[17..25].copy_from_slice(&self.count.amount.units().to_le_bytes());
+
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        let target = match buf.first()? {
+            0 => UpstreamType::Default,
+            1 => UpstreamType::Fallback,
+            _ => return None,
+        };
+
+        Some(Self {
+            target,
+            bucket_start: i64::from_le_bytes(buf.get(1..9)?.try_into().ok()?),
+            count: Count {
+                requests: u64::from_le_bytes(buf.get(9..17)?.try_into().ok()?),
+                amount: Money::from_units(i64::from_le_bytes(buf.get(17..25)?.try_into().ok()?)),
+            },
+        })
+    }
+}
+
+fn snapshot_path(dir: &Path, covers: u64) -> PathBuf {
+    dir.join(format!("{SNAPSHOT_PREFIX}{covers:020}{SNAPSHOT_SUFFIX}"))
+}
+
+/// `covers` of every snapshot in `dir`, newest first.
+pub fn snapshots(dir: &Path) -> Result<Vec<u64>, SnapshotError> {
+    let mut covers = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let seq = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(SNAPSHOT_PREFIX))
+            .and_then(|name| name.strip_suffix(SNAPSHOT_SUFFIX))
+            .and_then(|seq| seq.parse::<u64>().ok());
+
+        if let Some(seq) = seq {
+            covers.push(seq);
+        }
+    }
+
+    covers.sort_unstable_by(|a, b| b.cmp(a));
+
+    Ok(covers)
+}
+
+fn header(covers: u64, entries: u64, rollups: u64) -> [u8; HEADER_SIZE] {
+    let mut header = [0u8; HEADER_SIZE];
+
+    header[..4].copy_from_slice(MAGIC);
+    header[4..12].copy_from_slice(&covers.to_le_bytes());
+    header[12..20].copy_from_slice(&entries.to_le_bytes());
+    header[20..28].copy_from_slice(&rollups.to_le_bytes());
+    header[28..32].copy_from_slice(&rinha_conf::RINHA_MONEY_SCALE.to_le_bytes());
+
+    header
+}
+
+/// Streams a snapshot to a temporary file, every entry first and then every
+/// rollup, and renames it into place on `finish`, so a crash mid-write never
+/// leaves a half snapshot under a valid name. Nothing is buffered beyond the
+/// file's own write buffer.
+pub struct Writer {
+    dir: PathBuf,
+    covers: u64,
+    file: BufWriter<File>,
+    entries: u64,
+    rollups: u64,
+    /// checksum of everything after the header, which is only known once
+    /// every record is written
+    crc: crc32fast::Hasher,
+}
+
+impl Writer {
+    pub fn create(dir: &Path, covers: u64) -> Result<Self, SnapshotError> {
+        let mut file = BufWriter::new(File::create(
+            snapshot_path(dir, covers).with_extension("tmp"),
+        )?);
+        file.write_all(&[0u8; HEADER_SIZE])?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            covers,
+            file,
+            entries: 0,
+            rollups: 0,
+            crc: crc32fast::Hasher::new(),
+        })
+    }
+
+    pub fn entry(&mut self, entry: &Entry) -> Result<(), SnapshotError> {
+        debug_assert_eq!(self.rollups, 0, "entries go before rollups");
+
+        let buf = entry.encode();
+        self.crc.update(&buf);
+        self.file.write_all(&buf)?;
+        self.entries += 1;
+
+        Ok(())
+    }
+
+    pub fn rollup(&mut self, rollup: &Rollup) -> Result<(), SnapshotError> {
+        let buf = rollup.encode();
+        self.crc.update(&buf);
+        self.file.write_all(&buf)?;
+        self.rollups += 1;
+
+        Ok(())
+    }
+
+    /// Fills in the header and checksum, then moves the snapshot into place.
+    /// Returns how many entries and rollups it holds.
+    pub fn finish(self) -> Result<(u64, u64), SnapshotError> {
+        let header = header(self.covers, self.entries, self.rollups);
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(&header);
+        crc.combine(&self.crc);
+
+        let mut file = self.file.into_inner().map_err(|err| err.into_error())?;
+        file.write_all(&crc.finalize().to_le_bytes())?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&header)?;
+        file.sync_all()?;
+
+        let path = snapshot_path(&self.dir, self.covers);
+        fs::rename(path.with_extension("tmp"), &path)?;
+        File::open(&self.dir)?.sync_all()?;
+
+        Ok((self.entries, self.rollups))
+    }
+}
+
+/// Decodes a snapshot along with the money scale it was written with, when
+/// it recorded one.
+fn decode(buf: &[u8]) -> Option<(Snapshot, Option<u32>)> {
+    let (body, crc) = buf.split_at_checked(buf.len().checked_sub(4)?)?;
+
+    if crc32fast::hash(body) != u32::from_le_bytes(crc.try_into().ok()?) {
+        return None;
+    }
+
+    let header_size = match body.get(..4)? {
+        magic if magic == MAGIC => HEADER_SIZE,
+        magic if magic == MAGIC_V2 => HEADER_SIZE_V2,
+        magic if magic == MAGIC_V1 => HEADER_SIZE_V1,
+        _ => return None,
+    };
+    let (header, records) = body.split_at_checked(header_size)?;
+
+    let covers = u64::from_le_bytes(header[4..12].try_into().ok()?);
+    let count = u64::from_le_bytes(header[12..20].try_into().ok()?) as usize;
+    let rollup_count = match header.get(20..28) {
+        Some(rollup_count) => u64::from_le_bytes(rollup_count.try_into().ok()?) as usize,
+        None => 0,
+    };
+    let scale = match header.get(28..32) {
+        Some(scale) => Some(u32::from_le_bytes(scale.try_into().ok()?)),
+        None => None,
+    };
+
+    let (entries, rollups) = records.split_at_checked(count.checked_mul(RECORD_SIZE)?)?;
+    if rollups.len() != rollup_count.checked_mul(ROLLUP_SIZE)? {
+        return None;
+    }
+
+    let entries = entries
+        .chunks(RECORD_SIZE)
+        .map(Entry::decode)
+        .collect::<Option<Vec<_>>>()?;
+    let rollups = rollups
+        .chunks(ROLLUP_SIZE)
+        .map(Rollup::decode)
+        .collect::<Option<Vec<_>>>()?;
+
+    Some((
+        Snapshot {
+            covers,
+            entries,
+            rollups,
+        },
+        scale,
+    ))
+}
+
+/// Loads the newest snapshot that passes its checksum, skipping corrupt or
+/// truncated ones in favour of their predecessors. One written under another
+/// `RINHA_MONEY_SCALE` is refused rather than read with the wrong unit.
+pub fn load_latest(dir: &Path) -> Result<Option<Snapshot>, SnapshotError> {
+    for covers in snapshots(dir)? {
+        let buf = fs::read(snapshot_path(dir, covers))?;
+
+        match decode(&buf) {
+            Some((_, Some(scale))) if scale != *rinha_conf::RINHA_MONEY_SCALE => {
+                return Err(SnapshotError::ScaleMismatch { covers, scale });
+            }
+            Some((snapshot, _)) if snapshot.covers == covers => return Ok(Some(snapshot)),
+            _ => tracing::warn!(covers, "skipping corrupt snapshot"),
+        }
+    }
+
+    Ok(None)
+}
+
+/// Keeps the `retain` newest snapshots and deletes the rest, returning the
+/// `covers` of the oldest one kept once `retain` of them exist. Segments
+/// before it are no longer needed, even if every newer snapshot later turns
+/// out to be corrupt.
+pub fn prune(dir: &Path, retain: usize) -> Result<Option<u64>, SnapshotError> {
+    let covers = snapshots(dir)?;
+    let retain = retain.max(1);
+
+    if covers.len() < retain {
+        return Ok(None);
+    }
+
+    for stale in covers.iter().skip(retain) {
+        fs::remove_file(snapshot_path(dir, *stale))?;
+    }
+
+    Ok(Some(covers[retain - 1]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rinha_storage::{testing::TempDir, wal};
+    use uuid::Uuid;
+
+    const AT: i64 = 1_750_000_000_000_000;
+
+    fn entries(count: i64) -> Vec<Entry> {
+        (0..count)
+            .map(|idx| Entry {
+                target: if idx % 2 == 0 {
+                    UpstreamType::Default
+                } else {
+                    UpstreamType::Fallback
+                },
+                requested_at: AT + idx,
+                correlation_id: Uuid::new_v4(),
+                amount: Money::from_units(100 + idx),
+            })
+            .collect()
+    }
+
+    fn rollups() -> Vec<Rollup> {
+        vec![
+            Rollup {
+                target: UpstreamType::Default,
+                bucket_start: AT - 60_000_000,
+                count: Count {
+                    requests: 3,
+                    amount: Money::from_units(300),
+                },
+            },
+            Rollup {
+                target: UpstreamType::Fallback,
+                bucket_start: AT - 120_000_000,
+                count: Count {
+                    requests: 1,
+                    amount: Money::from_units(7),
+                },
+            },
+        ]
+    }
+
+    fn write(dir: &Path, covers: u64, entries: &[Entry], rollups: &[Rollup]) {
+        let mut writer = Writer::create(dir, covers).unwrap();
+        for entry in entries {
+            writer.entry(entry).unwrap();
+        }
+        for rollup in rollups {
+            writer.rollup(rollup).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    /// A snapshot in a format older than `MAGIC`: `header` follows the magic,
+    /// then the records, then the checksum of it all.
+    fn write_legacy(dir: &Path, covers: u64, magic: &[u8; 4], header: &[u64], records: &[u8]) {
+        let mut buf = magic.to_vec();
+        for field in header {
+            buf.extend_from_slice(&field.to_le_bytes());
+        }
+        buf.extend_from_slice(records);
+        buf.extend_from_slice(&crc32fast::hash(&buf).to_le_bytes());
+
+        fs::write(snapshot_path(dir, covers), buf).unwrap();
+    }
+
+    fn assert_rollups_eq(loaded: &[Rollup], expected: &[Rollup]) {
+        assert_eq!(loaded.len(), expected.len());
+        for (loaded, expected) in loaded.iter().zip(expected) {
+            assert_eq!(loaded.target, expected.target);
+            assert_eq!(loaded.bucket_start, expected.bucket_start);
+            assert_eq!(loaded.count.requests, expected.count.requests);
+            assert_eq!(loaded.count.amount, expected.count.amount);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = TempDir::new();
+        let (entries, rollups) = (entries(5), rollups());
+        write(&dir.0, 3, &entries, &rollups);
+
+        let snapshot = load_latest(&dir.0).unwrap().unwrap();
+        assert_eq!(snapshot.covers, 3);
+        assert_eq!(snapshot.entries, entries);
+        assert_rollups_eq(&snapshot.rollups, &rollups);
+    }
+
+    #[test]
+    fn corrupt_byte_falls_back_to_the_previous_snapshot_and_its_wal() {
+        let dir = TempDir::new();
+        let older = entries(2);
+        let logged = entries(3);
+        write(&dir.0, 1, &older, &[]);
+        write(&dir.0, 2, &[older.clone(), logged.clone()].concat(), &[]);
+        let mut wal = wal::Wal::create(&dir.0, 1, wal::FsyncPolicy::Never).unwrap();
+        wal.append(&logged).unwrap();
+
+        let path = snapshot_path(&dir.0, 2);
+        let mut buf = fs::read(&path).unwrap();
+        buf[HEADER_SIZE + 3] ^= 0x01;
+        fs::write(&path, buf).unwrap();
+
+        let snapshot = load_latest(&dir.0).unwrap().unwrap();
+        assert_eq!(snapshot.covers, 1);
+        assert_eq!(snapshot.entries, older);
+        assert_eq!(wal::replay(&dir.0, snapshot.covers).unwrap(), logged);
+    }
+
+    #[test]
+    fn short_snapshot_falls_back_to_the_previous_one() {
+        let dir = TempDir::new();
+        let older = entries(2);
+        write(&dir.0, 1, &older, &[]);
+        write(&dir.0, 2, &entries(4), &rollups());
+
+        let path = snapshot_path(&dir.0, 2);
+        let len = fs::metadata(&path).unwrap().len();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - ROLLUP_SIZE as u64)
+            .unwrap();
+
+        let snapshot = load_latest(&dir.0).unwrap().unwrap();
+        assert_eq!(snapshot.covers, 1);
+        assert_eq!(snapshot.entries, older);
+    }
+
+    #[test]
+    fn nothing_valid_loads_nothing() {
+        let dir = TempDir::new();
+        fs::write(snapshot_path(&dir.0, 1), b"RSN3").unwrap();
+
+        assert!(load_latest(&dir.0).unwrap().is_none());
+    }
+
+    #[test]
+    fn reads_rsn2() {
+        let dir = TempDir::new();
+        let (entries, rollups) = (entries(3), rollups());
+        let records: Vec<u8> = entries
+            .iter()
+            .flat_map(|entry| entry.encode())
+            .chain(rollups.iter().flat_map(|rollup| rollup.encode()))
+            .collect();
+        write_legacy(&dir.0, 7, MAGIC_V2, &[7, 3, 2], &records);
+
+        let snapshot = load_latest(&dir.0).unwrap().unwrap();
+        assert_eq!(snapshot.covers, 7);
+        assert_eq!(snapshot.entries, entries);
+        assert_rollups_eq(&snapshot.rollups, &rollups);
+    }
+
+    #[test]
+    fn reads_rsn1() {
+        let dir = TempDir::new();
+        let entries = entries(3);
+        let records: Vec<u8> = entries.iter().flat_map(|entry| entry.encode()).collect();
+        write_legacy(&dir.0, 7, MAGIC_V1, &[7, 3], &records);
+
+        let snapshot = load_latest(&dir.0).unwrap().unwrap();
+        assert_eq!(snapshot.covers, 7);
+        assert_eq!(snapshot.entries, entries);
+        assert!(snapshot.rollups.is_empty());
+    }
+
+    #[test]
+    fn other_money_scale_is_refused() {
+        let dir = TempDir::new();
+        write(&dir.0, 1, &entries(1), &[]);
+
+        let path = snapshot_path(&dir.0, 1);
+        let mut buf = fs::read(&path).unwrap();
+        let scale = *rinha_conf::RINHA_MONEY_SCALE + 1;
+        buf[28..32].copy_from_slice(&scale.to_le_bytes());
+        let body = buf.len() - 4;
+        let crc = crc32fast::hash(&buf[..body]);
+        buf[body..].copy_from_slice(&crc.to_le_bytes());
+        fs::write(&path, buf).unwrap();
+
+        assert!(matches!(
+            load_latest(&dir.0),
+            Err(SnapshotError::ScaleMismatch { covers: 1, .. })
+        ));
+    }
+}
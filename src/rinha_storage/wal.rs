@@ -0,0 +1,330 @@
+use crate::{rinha_ambulance::UpstreamType, rinha_conf, rinha_domain::Money, rinha_storage::Entry};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+use uuid::Uuid;
+
+/// `target (1) | requested_at (8) | correlation_id (16) | amount (8) | crc32 (4)`,
+/// integers little-endian; the checksum covers every byte before it.
+pub const RECORD_SIZE: usize = 37;
+const PAYLOAD_SIZE: usize = RECORD_SIZE - 4;
+
+/// Every segment starts with `magic (4) | money scale (4)`, so entries are
+/// never replayed under a `RINHA_MONEY_SCALE` other than the one they were
+/// written with. Segments from before the header start with an entry.
+const MAGIC: &[u8; 4] = b"RWAL";
+const HEADER_SIZE: usize = 4 + 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// `fsync` after every group of appended entries.
+    Always,
+    /// `fsync` from the storage task, at most once per configured interval.
+    Interval,
+    /// leave flushing entirely to the kernel.
+    Never,
+}
+
+impl FsyncPolicy {
+    pub fn parse(policy: &str) -> Self {
+        match policy {
+            "always" => Self::Always,
+            "never" => Self::Never,
+            _ => Self::Interval,
+        }
+    }
+}
+
+impl Entry {
+    pub fn encode(&self) -> [u8; RECORD_SIZE] {
+        let mut buf = [0u8; RECORD_SIZE];
+
+        buf[0] = match self.target {
+            UpstreamType::Default => 0,
+            UpstreamType::Fallback => 1,
+        };
+        buf[1..9].copy_from_slice(&self.requested_at.to_le_bytes());
+        buf[9..25].copy_from_slice(self.correlation_id.as_bytes());
+        buf[25..33].copy_from_slice(&self.amount.units().to_le_bytes());
+
+        let crc = crc32fast::hash(&buf[..PAYLOAD_SIZE]);
+        buf[PAYLOAD_SIZE..].copy_from_slice(&crc.to_le_bytes());
+
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        let buf: &[u8; RECORD_SIZE] = buf.try_into().ok()?;
+        let crc = u32::from_le_bytes(buf[PAYLOAD_SIZE..].try_into().ok()?);
+
+        if crc32fast::hash(&buf[..PAYLOAD_SIZE]) != crc {
+            return None;
+        }
+
+        let target = match buf[0] {
+            0 => UpstreamType::Default,
+            1 => UpstreamType::Fallback,
+            _ => return None,
+        };
+
+        Some(Self {
+            target,
+            requested_at: i64::from_le_bytes(buf[1..9].try_into().ok()?),
+            correlation_id: Uuid::from_bytes(buf[9..25].try_into().ok()?),
+            amount: Money::from_units(i64::from_le_bytes(buf[25..33].try_into().ok()?)),
+        })
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum WalError {
+    #[error("io")]
+    IO(#[from] std::io::Error),
+    #[error("money scale mismatch, segment {seq} uses {scale}")]
+    ScaleMismatch { seq: u64, scale: u32 },
+    #[error("writer stopped")]
+    Stopped,
+}
+
+const SEGMENT_PREFIX: &str = "wal-";
+const SEGMENT_SUFFIX: &str = ".log";
+
+fn segment_path(dir: &Path, seq: u64) -> PathBuf {
+    dir.join(format!("{SEGMENT_PREFIX}{seq:020}{SEGMENT_SUFFIX}"))
+}
+
+/// Sequence numbers of every segment in `dir`, in ascending order.
+pub fn segments(dir: &Path) -> Result<Vec<u64>, WalError> {
+    let mut seqs = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let seq = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(SEGMENT_PREFIX))
+            .and_then(|name| name.strip_suffix(SEGMENT_SUFFIX))
+            .and_then(|seq| seq.parse::<u64>().ok());
+
+        if let Some(seq) = seq {
+            seqs.push(seq);
+        }
+    }
+
+    seqs.sort_unstable();
+
+    Ok(seqs)
+}
+
+/// Decodes every intact entry of the segments numbered `from` onwards.
+/// A segment is read up to its first short or corrupt entry: a crash can
+/// only tear the tail of the segment that was active at the time.
+pub fn replay(dir: &Path, from: u64) -> Result<Vec<Entry>, WalError> {
+    let mut entries = Vec::new();
+
+    for seq in segments(dir)?.into_iter().filter(|seq| *seq >= from) {
+        let buf = fs::read(segment_path(dir, seq))?;
+        let before = entries.len();
+
+        let records = match buf.strip_prefix(MAGIC) {
+            Some(rest) => match rest.split_first_chunk::<4>() {
+                Some((scale, records)) => {
+                    let scale = u32::from_le_bytes(*scale);
+                    if scale != *rinha_conf::RINHA_MONEY_SCALE {
+                        return Err(WalError::ScaleMismatch { seq, scale });
+                    }
+                    records
+                }
+                // torn before the header was complete
+                None => &[],
+            },
+            None => &buf[..],
+        };
+
+        entries.extend(records.chunks(RECORD_SIZE).map_while(Entry::decode));
+
+        let valid_len = (entries.len() - before) * RECORD_SIZE;
+        if valid_len < records.len() {
+            tracing::warn!(
+                seq,
+                dropped = records.len() - valid_len,
+                "ignoring torn or corrupt wal tail"
+            );
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Removes every segment numbered below `before`.
+pub fn prune(dir: &Path, before: u64) -> Result<(), WalError> {
+    for seq in segments(dir)?.into_iter().filter(|seq| *seq < before) {
+        fs::remove_file(segment_path(dir, seq))?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct Wal {
+    dir: PathBuf,
+    seq: u64,
+    file: File,
+    policy: FsyncPolicy,
+    len: u64,
+    dirty: bool,
+}
+
+impl Wal {
+    /// Starts a fresh segment numbered `seq`. Appends never go to a segment
+    /// written by a previous run, so a torn tail is never followed by data.
+    pub fn create(dir: &Path, seq: u64, policy: FsyncPolicy) -> Result<Self, WalError> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(segment_path(dir, seq))?;
+
+        let mut header = [0u8; HEADER_SIZE];
+        header[..4].copy_from_slice(MAGIC);
+        header[4..].copy_from_slice(&rinha_conf::RINHA_MONEY_SCALE.to_le_bytes());
+        file.write_all(&header)?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            seq,
+            file,
+            policy,
+            len: HEADER_SIZE as u64,
+            dirty: false,
+        })
+    }
+
+    /// Appends `entries` with a single write, and a single `fsync` under
+    /// `Always`, so a group commit costs one disk flush however large.
+    pub fn append(&mut self, entries: &[Entry]) -> Result<(), WalError> {
+        let buf: Vec<u8> = entries.iter().flat_map(|entry| entry.encode()).collect();
+
+        if let Err(err) = self.file.write_all(&buf) {
+            // drop partially written entries so later appends stay aligned
+            self.file.set_len(self.len)?;
+            self.file.seek(SeekFrom::Start(self.len))?;
+            return Err(err.into());
+        }
+        self.len += buf.len() as u64;
+
+        match self.policy {
+            FsyncPolicy::Always => self.file.sync_data()?,
+            FsyncPolicy::Interval => self.dirty = true,
+            FsyncPolicy::Never => {}
+        }
+
+        Ok(())
+    }
+
+    pub fn sync(&mut self) -> Result<(), WalError> {
+        if self.dirty {
+            self.file.sync_data()?;
+            self.dirty = false;
+        }
+
+        Ok(())
+    }
+
+    /// Seals the active segment and switches appends to the next one,
+    /// returning the new sequence number.
+    pub fn rotate(&mut self) -> Result<u64, WalError> {
+        self.file.sync_data()?;
+        *self = Self::create(&self.dir, self.seq + 1, self.policy)?;
+
+        Ok(self.seq)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rinha_storage::testing::TempDir;
+
+    fn entries(count: i64) -> Vec<Entry> {
+        (0..count)
+            .map(|idx| Entry {
+                target: UpstreamType::Default,
+                requested_at: 1_750_000_000_000_000 + idx,
+                correlation_id: Uuid::new_v4(),
+                amount: Money::from_units(100 + idx),
+            })
+            .collect()
+    }
+
+    fn write_segment(dir: &Path, seq: u64, entries: &[Entry]) {
+        let mut wal = Wal::create(dir, seq, FsyncPolicy::Never).unwrap();
+        wal.append(entries).unwrap();
+    }
+
+    #[test]
+    fn replay_stops_at_the_last_intact_record() {
+        let dir = TempDir::new();
+        let written = entries(3);
+        write_segment(&dir.0, 0, &written);
+
+        let path = segment_path(&dir.0, 0);
+        let len = fs::metadata(&path).unwrap().len();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - RECORD_SIZE as u64 / 2)
+            .unwrap();
+
+        assert_eq!(replay(&dir.0, 0).unwrap(), written[..2]);
+    }
+
+    #[test]
+    fn replay_skips_a_corrupt_record_and_what_follows_it() {
+        let dir = TempDir::new();
+        let written = entries(3);
+        write_segment(&dir.0, 0, &written);
+
+        let path = segment_path(&dir.0, 0);
+        let mut buf = fs::read(&path).unwrap();
+        buf[HEADER_SIZE + RECORD_SIZE + 10] ^= 0xff;
+        fs::write(&path, buf).unwrap();
+
+        assert_eq!(replay(&dir.0, 0).unwrap(), written[..1]);
+    }
+
+    #[test]
+    fn torn_segment_does_not_hide_the_next_one() {
+        let dir = TempDir::new();
+        let first = entries(2);
+        let second = entries(2);
+        write_segment(&dir.0, 0, &first);
+        write_segment(&dir.0, 1, &second);
+
+        let path = segment_path(&dir.0, 0);
+        let len = fs::metadata(&path).unwrap().len();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+
+        let replayed = replay(&dir.0, 0).unwrap();
+        assert_eq!(replayed[..1], first[..1]);
+        assert_eq!(replayed[1..], second[..]);
+    }
+
+    #[test]
+    fn replay_starts_at_the_covered_segment() {
+        let dir = TempDir::new();
+        let first = entries(2);
+        let second = entries(1);
+        write_segment(&dir.0, 4, &first);
+        write_segment(&dir.0, 5, &second);
+
+        assert_eq!(replay(&dir.0, 5).unwrap(), second);
+    }
+}
@@ -1,13 +1,14 @@
 use crate::{
-    rinha_ambulance::{self, Upstream, UpstreamType},
-    rinha_chan,
-    rinha_domain::{Payment, dt_to_i64},
+    rinha_ambulance::{self, Upstream},
+    rinha_chan::{self, Queued},
+    rinha_events::{self, Kind},
+    rinha_metrics,
     rinha_net::{self, JSON_CONTENT_TYPE},
-    rinha_storage,
+    rinha_status, rinha_storage,
 };
 use http_body_util::Full;
 use hyper::{Method, Request, body::Bytes, header};
-use tokio::time::{Duration, sleep};
+use tokio::time::{Duration, Instant, sleep};
 
 #[derive(thiserror::Error, Debug)]
 pub enum PaymentError {
@@ -26,15 +27,29 @@ pub enum PaymentError {
     ServerFailed,
 }
 
-async fn try_process_payment(payment: &Payment, upstream: &Upstream) -> Result<(), PaymentError> {
+async fn try_process_payment(
+    queued: &Queued,
+    upstream: &Upstream,
+    attempt: u32,
+) -> Result<(), PaymentError> {
+    let payment = &queued.payment;
     let upstream_type = upstream
         .ext
         .get::<rinha_ambulance::UpstreamType>()
         .ok_or_else(|| PaymentError::NoUpstreamTypeExt)?;
 
+    rinha_status::in_flight(payment.correlation_id, upstream_type);
+    rinha_events::publish(
+        Kind::Dispatched,
+        payment.correlation_id,
+        Some(upstream_type),
+        Some(attempt),
+    );
+
     let client = rinha_net::get_client();
     let uri = format!("http://{}/payments", upstream.addr);
     let payment_ser = serde_json::to_string(&payment)?;
+    let started = Instant::now();
     let res = client
         .request(
             Request::builder()
@@ -43,16 +58,38 @@ async fn try_process_payment(payment: &Payment, upstream: &Upstream) -> Result<(
                 .uri(uri)
                 .body(Full::<Bytes>::from(payment_ser))?,
         )
-        .await?;
-    let status = res.status();
+        .await;
+    rinha_metrics::upstream_latency(upstream_type, started.elapsed());
+    let status = res?.status();
 
     if status.is_success() {
-        let storage = match upstream_type {
-            UpstreamType::Default => rinha_storage::get_default_storage(),
-            UpstreamType::Fallback => rinha_storage::get_fallback_storage(),
-        };
-        let mut storage = storage.write().await;
-        storage.insert(dt_to_i64(payment.requested_at), payment.amount);
+        let storage = rinha_storage::get_storage();
+        rinha_chan::record_if_current(queued, || {
+            match storage.record(upstream_type, payment) {
+                Ok(true) => {
+                    rinha_status::processed(payment.correlation_id);
+                    rinha_metrics::processed(upstream_type);
+                    rinha_events::publish(
+                        Kind::Processed,
+                        payment.correlation_id,
+                        Some(upstream_type),
+                        Some(attempt),
+                    );
+                }
+                // already recorded, and counted, under this correlation id
+                Ok(false) => rinha_status::processed(payment.correlation_id),
+                Err(err) => {
+                    tracing::error!(?err, "storage record");
+                    rinha_status::failed(payment.correlation_id);
+                    rinha_events::publish(
+                        Kind::Failed,
+                        payment.correlation_id,
+                        Some(upstream_type),
+                        Some(attempt),
+                    );
+                }
+            }
+        });
 
         return Ok(());
     }
@@ -61,16 +98,38 @@ async fn try_process_payment(payment: &Payment, upstream: &Upstream) -> Result<(
         return Err(PaymentError::ServerFailed);
     }
 
+    rinha_status::given_up(payment.correlation_id);
+    rinha_metrics::failed(upstream_type);
+    rinha_events::publish(
+        Kind::Failed,
+        payment.correlation_id,
+        Some(upstream_type),
+        Some(attempt),
+    );
+
     Ok(())
 }
 
-async fn process_payment(payment: &Payment) {
+async fn process_payment(queued: &Queued) {
     let mut upstream_attempt: u32 = 0;
     let mut payment_attempt: u32 = 0;
+    let mut attempt: u32 = 0;
 
-    loop {
+    // a purge gives up on the retries of every payment queued before it
+    while queued.is_current() {
         if let Some(upstream) = rinha_ambulance::select().await {
-            if let Err(err) = try_process_payment(&payment, &upstream).await {
+            attempt += 1;
+
+            if let Err(err) = try_process_payment(queued, upstream, attempt).await {
+                rinha_status::retrying(queued.payment.correlation_id);
+                rinha_metrics::retried();
+                rinha_events::publish(
+                    Kind::Retried,
+                    queued.payment.correlation_id,
+                    None,
+                    Some(attempt),
+                );
+
                 if let PaymentError::ServerFailed = err {
                     let health_map = rinha_ambulance::get_health_map();
                     health_map.insert(upstream.hash_addr(), false);
@@ -118,8 +177,12 @@ async fn workers() {
                 let mut receiver = receiver.lock().await;
 
                 loop {
-                    if let Some(payment) = receiver.recv().await {
-                        process_payment(&payment).await;
+                    if let Some(queued) = receiver.recv().await {
+                        rinha_chan::drained();
+
+                        if queued.is_current() {
+                            process_payment(&queued).await;
+                        }
                     }
                 }
             }